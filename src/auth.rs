@@ -0,0 +1,386 @@
+#[cfg(feature = "jwt")]
+pub mod jwt;
+
+use std::future::Future;
+
+use base64::{Engine, prelude::BASE64_STANDARD};
+use bytes::{BufMut, Bytes, BytesMut};
+use md5::{Digest, Md5};
+
+use crate::info::HandshakeInfo;
+
+#[derive(Debug, Default)]
+pub struct Rejection {
+    pub(crate) challenge: Option<String>,
+}
+
+impl Rejection {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_challenge(challenge: impl Into<String>) -> Self {
+        let challenge = Some(challenge.into());
+
+        Self { challenge }
+    }
+
+    pub(crate) fn into_raw_response(self) -> Bytes {
+        let mut buf = BytesMut::new();
+
+        buf.put(&b"HTTP/1.1 401 Unauthorized\r\n"[..]);
+        if let Some(challenge) = self.challenge {
+            buf.put(&b"WWW-Authenticate: "[..]);
+            buf.put(challenge.as_bytes());
+            buf.put(&b"\r\n"[..]);
+        }
+        buf.put(&b"\r\n"[..]);
+
+        buf.into()
+    }
+}
+
+pub trait Authenticator {
+    fn authenticate(
+        &self,
+        info: &mut HandshakeInfo,
+    ) -> impl Future<Output = Result<(), Rejection>> + Send;
+
+    fn redacted_query_param(&self) -> Option<&str> {
+        None
+    }
+}
+
+impl Authenticator for () {
+    async fn authenticate(&self, _info: &mut HandshakeInfo) -> Result<(), Rejection> {
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct QueryTokenAuthenticator<F> {
+    parameter: String,
+    validate: F,
+}
+
+impl<F, Fut> QueryTokenAuthenticator<F>
+where
+    F: Fn(String) -> Fut + Send + Sync,
+    Fut: Future<Output = Result<(), Rejection>> + Send,
+{
+    pub fn new(parameter: impl Into<String>, validate: F) -> Self {
+        let parameter = parameter.into();
+
+        Self {
+            parameter,
+            validate,
+        }
+    }
+}
+
+impl<F, Fut> Authenticator for QueryTokenAuthenticator<F>
+where
+    F: Fn(String) -> Fut + Send + Sync,
+    Fut: Future<Output = Result<(), Rejection>> + Send,
+{
+    async fn authenticate(&self, info: &mut HandshakeInfo) -> Result<(), Rejection> {
+        let token = info
+            .query_param(&self.parameter)
+            .map(|token| token.into_owned());
+        info.remove_query_param(&self.parameter);
+
+        let token = token.ok_or_else(Rejection::new)?;
+
+        (self.validate)(token).await
+    }
+
+    fn redacted_query_param(&self) -> Option<&str> {
+        Some(&self.parameter)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Credentials {
+    username: String,
+    password: String,
+}
+
+impl Credentials {
+    pub fn new(username: impl Into<String>, password: impl Into<String>) -> Self {
+        let username = username.into();
+        let password = password.into();
+
+        Self { username, password }
+    }
+
+    pub(crate) fn authorization(
+        &self,
+        challenges: &[String],
+        method: &str,
+        uri: &str,
+    ) -> Option<String> {
+        let challenges: Vec<_> = challenges
+            .iter()
+            .flat_map(|header| split_challenges(header))
+            .collect();
+
+        let digest = challenges.iter().find_map(|challenge| {
+            let (scheme, params) = challenge.split_once(' ')?;
+
+            scheme
+                .eq_ignore_ascii_case("Digest")
+                .then(|| self.digest(params, method, uri))
+                .flatten()
+        });
+
+        digest.or_else(|| {
+            challenges
+                .iter()
+                .any(|challenge| {
+                    let scheme = challenge.split(' ').next().unwrap_or_default();
+
+                    scheme.eq_ignore_ascii_case("Basic")
+                })
+                .then(|| self.basic())
+        })
+    }
+
+    fn basic(&self) -> String {
+        let encoded = BASE64_STANDARD.encode(format!("{}:{}", self.username, self.password));
+
+        format!("Basic {encoded}")
+    }
+
+    fn digest(&self, params: &str, method: &str, uri: &str) -> Option<String> {
+        let cnonce = hex(&rand::random::<[u8; 8]>());
+
+        self.digest_with_cnonce(params, method, uri, &cnonce)
+    }
+
+    fn digest_with_cnonce(
+        &self,
+        params: &str,
+        method: &str,
+        uri: &str,
+        cnonce: &str,
+    ) -> Option<String> {
+        let params = parse_challenge_params(params);
+        let param = |name: &str| {
+            params
+                .iter()
+                .find(|(key, _)| key.eq_ignore_ascii_case(name))
+                .map(|(_, value)| value.as_str())
+        };
+
+        if param("algorithm").is_some_and(|algorithm| !algorithm.eq_ignore_ascii_case("MD5")) {
+            return None;
+        }
+
+        let realm = param("realm")?;
+        let nonce = param("nonce")?;
+        let qop =
+            param("qop").and_then(|qop| qop.split(',').map(str::trim).find(|qop| *qop == "auth"));
+
+        let ha1 = md5_hex(format!("{}:{realm}:{}", self.username, self.password));
+        let ha2 = md5_hex(format!("{method}:{uri}"));
+
+        let mut authorization = format!(
+            "Digest username=\"{}\", realm=\"{realm}\", nonce=\"{nonce}\", uri=\"{uri}\"",
+            self.username
+        );
+        let response = match qop {
+            Some(qop) => {
+                authorization.push_str(&format!(", qop={qop}, nc=00000001, cnonce=\"{cnonce}\""));
+
+                md5_hex(format!("{ha1}:{nonce}:00000001:{cnonce}:{qop}:{ha2}"))
+            }
+            None => md5_hex(format!("{ha1}:{nonce}:{ha2}")),
+        };
+        authorization.push_str(&format!(", response=\"{response}\""));
+        if let Some(opaque) = param("opaque") {
+            authorization.push_str(&format!(", opaque=\"{opaque}\""));
+        }
+
+        Some(authorization)
+    }
+}
+
+fn split_unquoted(value: &str) -> Vec<&str> {
+    let mut segments = Vec::new();
+    let mut start = 0;
+    let mut quoted = false;
+    let mut escaped = false;
+
+    for (i, c) in value.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            ',' if !quoted => {
+                segments.push(&value[start..i]);
+                start = i + 1;
+            }
+            _ => (),
+        }
+    }
+    segments.push(&value[start..]);
+
+    segments
+}
+
+fn split_challenges(header: &str) -> Vec<String> {
+    let mut challenges: Vec<String> = Vec::new();
+
+    for segment in split_unquoted(header) {
+        let segment = segment.trim();
+        if segment.is_empty() {
+            continue;
+        }
+
+        let token_end = segment
+            .find(|c: char| c == '=' || c.is_whitespace())
+            .unwrap_or(segment.len());
+        let is_param = segment[token_end..].trim_start().starts_with('=');
+
+        match challenges.last_mut() {
+            Some(challenge) if is_param => {
+                challenge.push_str(", ");
+                challenge.push_str(segment);
+            }
+            _ => challenges.push(segment.to_string()),
+        }
+    }
+
+    challenges
+}
+
+fn parse_challenge_params(params: &str) -> Vec<(String, String)> {
+    let mut parsed = Vec::new();
+    let mut rest = params.trim();
+
+    while let Some((key, after)) = rest.split_once('=') {
+        let key = key.trim().trim_start_matches(',').trim().to_string();
+        let after = after.trim_start();
+
+        let (value, remaining) = if let Some(quoted) = after.strip_prefix('"') {
+            let end = quoted.find('"').unwrap_or(quoted.len());
+
+            (&quoted[..end], quoted.get(end + 1..).unwrap_or_default())
+        } else {
+            let end = after.find(',').unwrap_or(after.len());
+
+            (after[..end].trim(), &after[end..])
+        };
+
+        parsed.push((key, value.to_string()));
+        rest = remaining.trim_start().trim_start_matches(',');
+    }
+
+    parsed
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn md5_hex(data: String) -> String {
+    hex(&Md5::digest(data.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mufasa() -> Credentials {
+        Credentials::new("Mufasa", "Circle Of Life")
+    }
+
+    #[test]
+    fn digest_matches_rfc_2617_example() {
+        let params = "realm=\"testrealm@host.com\", qop=\"auth,auth-int\", \
+                      nonce=\"dcd98b7102dd2f0e8b11d0f600bfb0c093\", \
+                      opaque=\"5ccc069c403ebaf9f0171e9517f40e41\"";
+
+        let authorization =
+            mufasa().digest_with_cnonce(params, "GET", "/dir/index.html", "0a4f113b");
+
+        assert_eq!(
+            authorization.as_deref(),
+            Some(
+                "Digest username=\"Mufasa\", realm=\"testrealm@host.com\", \
+                 nonce=\"dcd98b7102dd2f0e8b11d0f600bfb0c093\", uri=\"/dir/index.html\", \
+                 qop=auth, nc=00000001, cnonce=\"0a4f113b\", \
+                 response=\"6629fae49393a05397450978507c4ef1\", \
+                 opaque=\"5ccc069c403ebaf9f0171e9517f40e41\""
+            )
+        );
+    }
+
+    #[test]
+    fn digest_without_qop() {
+        let params = "realm=\"testrealm@host.com\", nonce=\"dcd98b7102dd2f0e8b11d0f600bfb0c093\"";
+
+        let authorization = mufasa().digest(params, "GET", "/dir/index.html").unwrap();
+
+        assert!(authorization.ends_with("response=\"670fd8c2df070c60b045671b8b24ff02\""));
+        assert!(!authorization.contains("cnonce"));
+    }
+
+    #[test]
+    fn digest_rejects_unknown_algorithm() {
+        let params = "realm=\"r\", nonce=\"n\", algorithm=SHA-256";
+
+        assert_eq!(mufasa().digest(params, "GET", "/"), None);
+    }
+
+    #[test]
+    fn challenge_params_keep_quoted_commas() {
+        let params = parse_challenge_params("realm=\"a, b\", qop=\"auth,auth-int\", stale=false");
+
+        assert_eq!(
+            params,
+            [
+                ("realm".to_string(), "a, b".to_string()),
+                ("qop".to_string(), "auth,auth-int".to_string()),
+                ("stale".to_string(), "false".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn one_header_with_several_challenges_is_split() {
+        let challenges = split_challenges(
+            "Basic realm=\"a, b\", Digest realm=\"c\", qop=\"auth,auth-int\", nonce=\"n\", Negotiate",
+        );
+
+        assert_eq!(
+            challenges,
+            [
+                "Basic realm=\"a, b\"",
+                "Digest realm=\"c\", qop=\"auth,auth-int\", nonce=\"n\"",
+                "Negotiate",
+            ]
+        );
+    }
+
+    #[test]
+    fn digest_is_preferred_over_basic_in_one_header() {
+        let challenges = ["Basic realm=\"a\", Digest realm=\"b\", nonce=\"n\"".to_string()];
+
+        let authorization = mufasa().authorization(&challenges, "GET", "/").unwrap();
+
+        assert!(authorization.starts_with("Digest username=\"Mufasa\", realm=\"b\", nonce=\"n\""));
+    }
+
+    #[test]
+    fn basic_is_used_without_digest() {
+        let challenges = ["Basic realm=\"a\"".to_string()];
+
+        let authorization = mufasa().authorization(&challenges, "GET", "/");
+
+        assert_eq!(
+            authorization.as_deref(),
+            Some("Basic TXVmYXNhOkNpcmNsZSBPZiBMaWZl")
+        );
+    }
+}
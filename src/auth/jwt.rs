@@ -0,0 +1,79 @@
+use std::marker::PhantomData;
+
+use serde::de::DeserializeOwned;
+
+pub use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+
+use crate::{
+    auth::{Authenticator, Rejection},
+    info::HandshakeInfo,
+};
+
+#[derive(Clone)]
+pub struct JwtAuthenticator<C> {
+    keys: Vec<DecodingKey>,
+    validation: Validation,
+    query_parameter: Option<String>,
+    claims: PhantomData<fn() -> C>,
+}
+
+impl<C> JwtAuthenticator<C> {
+    pub fn new(key: DecodingKey, validation: Validation) -> Self {
+        Self {
+            keys: vec![key],
+            validation,
+            query_parameter: None,
+            claims: PhantomData,
+        }
+    }
+
+    pub fn with_key(mut self, key: DecodingKey) -> Self {
+        self.keys.push(key);
+
+        self
+    }
+
+    pub fn with_query_parameter(mut self, name: impl Into<String>) -> Self {
+        self.query_parameter = Some(name.into());
+
+        self
+    }
+
+    fn extract_token<'i>(&self, info: &'i HandshakeInfo) -> Option<&'i str> {
+        let from_header = info
+            .header("Authorization")
+            .and_then(|value| std::str::from_utf8(value).ok())
+            .and_then(|value| value.split_once(' '))
+            .filter(|(scheme, _)| scheme.eq_ignore_ascii_case("Bearer"))
+            .map(|(_, token)| token.trim());
+
+        from_header.or_else(|| {
+            let name = self.query_parameter.as_deref()?;
+
+            info.query()?
+                .split('&')
+                .filter_map(|pair| pair.split_once('='))
+                .find(|(key, _)| *key == name)
+                .map(|(_, token)| token)
+        })
+    }
+}
+
+impl<C: DeserializeOwned + Send + Sync + 'static> Authenticator for JwtAuthenticator<C> {
+    async fn authenticate(&self, info: &mut HandshakeInfo) -> Result<(), Rejection> {
+        let token = self
+            .extract_token(info)
+            .ok_or_else(|| Rejection::with_challenge("Bearer"))?;
+
+        let claims = self
+            .keys
+            .iter()
+            .find_map(|key| jsonwebtoken::decode::<C>(token, key, &self.validation).ok())
+            .ok_or_else(|| Rejection::with_challenge("Bearer error=\"invalid_token\""))?
+            .claims;
+
+        info.extensions_mut().insert(claims);
+
+        Ok(())
+    }
+}
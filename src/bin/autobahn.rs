@@ -0,0 +1,78 @@
+use std::net::SocketAddr;
+
+use tokio::net::TcpListener;
+use websocket::{connection::Connection, error::WebSocketError, gateway};
+
+const AGENT: &str = "websocket-rs";
+
+async fn echo(connection: Connection) {
+    while let Ok(message) = connection.receive().await {
+        if connection.send(message).await.is_err() {
+            break;
+        }
+    }
+}
+
+async fn run_server(addr: SocketAddr) -> Result<(), WebSocketError> {
+    let listener = TcpListener::bind(addr).await?;
+
+    loop {
+        let (stream, _) = listener.accept().await?;
+
+        tokio::spawn(async move {
+            if let Ok(connection) = gateway::accept(stream).await {
+                echo(connection).await;
+            }
+        });
+    }
+}
+
+async fn run_client(addr: SocketAddr) -> Result<(), WebSocketError> {
+    let connection = gateway::connect_with_path(addr, "/getCaseCount").await?;
+    let cases: usize = connection
+        .receive()
+        .await?
+        .unwrap_text()
+        .parse()
+        .expect("invalid case count");
+
+    for case in 1..=cases {
+        let path = format!("/runCase?case={case}&agent={AGENT}");
+
+        match gateway::connect_with_path(addr, &path).await {
+            Ok(connection) => echo(connection).await,
+            Err(e) => eprintln!("case {case} failed to connect: {e}"),
+        }
+    }
+
+    let path = format!("/updateReports?agent={AGENT}");
+    let connection = gateway::connect_with_path(addr, &path).await?;
+    while connection.receive().await.is_ok() {}
+
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() {
+    let mode = std::env::args().nth(1).unwrap_or_default();
+    let addr = std::env::args()
+        .nth(2)
+        .unwrap_or_else(|| "127.0.0.1:9001".to_string())
+        .parse()
+        .expect("invalid socket address");
+
+    let result = match mode.as_str() {
+        "server" => run_server(addr).await,
+        "client" => run_client(addr).await,
+        _ => {
+            eprintln!("usage: autobahn <server|client> [address]");
+
+            return;
+        }
+    };
+
+    if let Err(e) = result {
+        eprintln!("autobahn {mode} failed: {e}");
+        std::process::exit(1);
+    }
+}
@@ -1,19 +1,44 @@
 #![allow(dead_code)]
 
-use bytes::{Bytes, BytesMut};
+use std::{
+    cmp::Ordering as CmpOrdering,
+    collections::BinaryHeap,
+    ops::ControlFlow,
+    pin::Pin,
+    sync::{
+        Arc, Mutex, OnceLock,
+        atomic::{AtomicU8, AtomicU64, Ordering},
+    },
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
 use rayon::iter::{IndexedParallelIterator, IntoParallelRefMutIterator, ParallelIterator};
 use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt},
-    net::TcpStream,
+    io::{AsyncRead, AsyncReadExt, AsyncWriteExt, BufWriter, ReadBuf},
+    net::{
+        TcpStream,
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+    },
 };
 
 use crate::{
-    error::{InvalidFrame, WebSocketError},
-    message::{Binary, Message, Text},
+    error::{ErrorKind, InvalidFrame, WebSocketError},
+    extensions::Extensions,
+    handshake::{PROTOCOL_VERSION, is_sensitive_header, redact_raw_http},
+    info::{HandshakeInfo, HttpExchange, percent_encode},
+    message::{Binary, Envelope, Message, Text},
 };
 
 pub(crate) const MAX_FRAME_PAYLOAD_SIZE: usize = 16 * 1024 * 1024;
 pub(crate) const MAX_MESSAGE_SIZE: usize = 64 * 1024 * 1024;
+pub(crate) const MIN_FRAGMENT_SIZE: usize = 4;
+pub(crate) const MAX_CONTROL_PAYLOAD_SIZE: usize = 125;
+const MAX_FRAME_HEADER_SIZE: usize = 14;
+const MASKING_CHUNK_SIZE: usize = 4 * 1024;
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
+const WORK_BUDGET: usize = 128;
 
 #[derive(Debug, Clone, Copy)]
 enum Opcode {
@@ -63,34 +88,71 @@ struct RawFrame {
 }
 
 #[derive(Debug)]
-enum StatusCode {
+struct ReceivedFrame {
+    raw_frame: RawFrame,
+    wire_size: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(from = "u16", into = "u16"))]
+pub enum StatusCode {
     NormalClosure,
     GoingAway,
     ProtocolError,
     UnkownType,
+    Reserved,
+    NoStatusReceived,
+    AbnormalClosure,
     InconsistentData,
     PolicyViolation,
     MessageTooBig,
+    MandatoryExtension,
     UnexpectedCondition,
+    ServiceRestart,
+    TryAgainLater,
+    BadGateway,
+    TlsHandshake,
+    Other(u16),
 }
 
-impl TryFrom<u16> for StatusCode {
-    type Error = InvalidFrame;
+impl StatusCode {
+    pub fn is_valid(&self) -> bool {
+        matches!(u16::from(*self), 1000..=1003 | 1007..=1014 | 3000..=4999)
+    }
+}
 
-    fn try_from(raw: u16) -> Result<Self, Self::Error> {
-        let code = match raw {
+impl From<u16> for StatusCode {
+    fn from(raw: u16) -> Self {
+        match raw {
             1000 => Self::NormalClosure,
             1001 => Self::GoingAway,
             1002 => Self::ProtocolError,
             1003 => Self::UnkownType,
+            1004 => Self::Reserved,
+            1005 => Self::NoStatusReceived,
+            1006 => Self::AbnormalClosure,
             1007 => Self::InconsistentData,
             1008 => Self::PolicyViolation,
             1009 => Self::MessageTooBig,
+            1010 => Self::MandatoryExtension,
             1011 => Self::UnexpectedCondition,
-            _ => return Err(InvalidFrame::Code(raw)),
-        };
+            1012 => Self::ServiceRestart,
+            1013 => Self::TryAgainLater,
+            1014 => Self::BadGateway,
+            1015 => Self::TlsHandshake,
+            _ => Self::Other(raw),
+        }
+    }
+}
 
-        Ok(code)
+impl From<&InvalidFrame> for StatusCode {
+    fn from(error: &InvalidFrame) -> Self {
+        match error {
+            InvalidFrame::Text(_) => Self::InconsistentData,
+            InvalidFrame::PayloadSize | InvalidFrame::PayloadRejected(_) => Self::MessageTooBig,
+            _ => Self::ProtocolError,
+        }
     }
 }
 
@@ -101,10 +163,19 @@ impl From<StatusCode> for u16 {
             StatusCode::GoingAway => 1001,
             StatusCode::ProtocolError => 1002,
             StatusCode::UnkownType => 1003,
+            StatusCode::Reserved => 1004,
+            StatusCode::NoStatusReceived => 1005,
+            StatusCode::AbnormalClosure => 1006,
             StatusCode::InconsistentData => 1007,
             StatusCode::PolicyViolation => 1008,
             StatusCode::MessageTooBig => 1009,
-            StatusCode::UnexpectedCondition => unreachable!(),
+            StatusCode::MandatoryExtension => 1010,
+            StatusCode::UnexpectedCondition => 1011,
+            StatusCode::ServiceRestart => 1012,
+            StatusCode::TryAgainLater => 1013,
+            StatusCode::BadGateway => 1014,
+            StatusCode::TlsHandshake => 1015,
+            StatusCode::Other(raw) => raw,
         }
     }
 }
@@ -131,6 +202,55 @@ struct CloseContent {
     pub reason: Option<Text>,
 }
 
+fn truncate_reason(reason: Text, max_size: usize) -> Text {
+    if reason.len() <= max_size {
+        return reason;
+    }
+
+    let end = (0..=max_size)
+        .rev()
+        .find(|&end| reason.is_char_boundary(end))
+        .unwrap_or(0);
+
+    Text(reason.0.slice(..end))
+}
+
+impl TryFrom<Bytes> for CloseContent {
+    type Error = InvalidFrame;
+
+    fn try_from(payload: Bytes) -> Result<Self, Self::Error> {
+        if payload.len() < 2 {
+            return Err(InvalidFrame::Inconsistent);
+        }
+
+        let status = StatusCode::from(u16::from_be_bytes([payload[0], payload[1]]));
+        if !status.is_valid() {
+            return Err(InvalidFrame::Code(status.into()));
+        }
+        let reason = if payload.len() > 2 {
+            Some(payload[2..].try_into()?)
+        } else {
+            None
+        };
+
+        Ok(CloseContent { status, reason })
+    }
+}
+
+impl From<CloseContent> for Bytes {
+    fn from(content: CloseContent) -> Self {
+        let reason_length = content.reason.as_ref().map_or(0, |reason| reason.len());
+        let mut payload = BytesMut::with_capacity(2 + reason_length);
+
+        payload.put_u16(content.status.into());
+        if let Some(reason) = content.reason {
+            payload.put(reason.0);
+        }
+
+        payload.into()
+    }
+}
+
 #[derive(Debug)]
 enum Frame {
     Text(TextContent),
@@ -156,12 +276,75 @@ impl Frame {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 enum Mask {
     ClientSide,
     ServerSide,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Role {
+    Client,
+    Server,
+}
+
+impl From<Mask> for Role {
+    fn from(mask: Mask) -> Self {
+        match mask {
+            Mask::ClientSide => Role::Client,
+            Mask::ServerSide => Role::Server,
+        }
+    }
+}
+
+fn put_frame_header(
+    header: &mut BytesMut,
+    fin: bool,
+    opcode: Opcode,
+    payload_length: usize,
+    masking_key: Option<u32>,
+) {
+    let fin = if fin { 1 } else { 0 };
+    let opcode: u8 = opcode.into();
+    header.put_u8((fin << 7) | opcode);
+
+    let masked = if masking_key.is_some() { 1 } else { 0 };
+    let mut octet = masked << 7;
+    octet |= match payload_length {
+        (0..=125) => payload_length as u8,
+        (126..=0xFFFF) => 126,
+        _ => 127,
+    };
+    header.put_u8(octet);
+
+    match payload_length {
+        (0..=125) => (),
+        (126..=0xFFFF) => header.put_u16(payload_length as u16),
+        _ => header.put_u64(payload_length as u64),
+    }
+
+    if let Some(masking_key) = masking_key {
+        header.put_u32(masking_key);
+    }
+}
+
+pub(crate) fn encode_client_frame(message: Message) -> Bytes {
+    let (opcode, payload) = match message {
+        Message::Text(text) => (Opcode::Text, text.0),
+        Message::Binary(binary) => (Opcode::Binary, binary.0),
+    };
+    let masking_key = rand::random::<u32>();
+
+    let mut frame = BytesMut::with_capacity(MAX_FRAME_HEADER_SIZE + payload.len());
+    put_frame_header(&mut frame, true, opcode, payload.len(), Some(masking_key));
+    let header_size = frame.len();
+    frame.put(payload);
+    xor_payload(masking_key, &mut frame[header_size..]);
+
+    frame.freeze()
+}
+
 fn xor_payload(masking_key: u32, payload: &mut [u8]) {
     let masking_key = masking_key.to_be_bytes();
     payload
@@ -170,209 +353,2695 @@ fn xor_payload(masking_key: u32, payload: &mut [u8]) {
         .for_each(|(i, b)| *b ^= masking_key[i % 4]);
 }
 
-#[derive(Debug)]
-struct Controller {
-    send_tx: flume::Sender<Message>,
-    receive_rx: flume::Receiver<Message>,
-    stop_tx: tokio::sync::oneshot::Sender<()>,
-    handle: tokio::task::JoinHandle<()>,
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum MessageKind {
+    Text,
+    Binary,
 }
 
-impl Controller {
-    async fn send(&self, message: Message) -> Result<(), WebSocketError> {
-        if message.size() > MAX_MESSAGE_SIZE {
-            return Err(WebSocketError::InvalidMessageSize);
+impl From<MessageKind> for Opcode {
+    fn from(kind: MessageKind) -> Self {
+        match kind {
+            MessageKind::Text => Opcode::Text,
+            MessageKind::Binary => Opcode::Binary,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Utf8Validator {
+    pending: Vec<u8>,
+}
+
+impl Utf8Validator {
+    fn feed(&mut self, mut chunk: &[u8]) -> Result<(), std::str::Utf8Error> {
+        while !self.pending.is_empty() && !chunk.is_empty() {
+            self.pending.push(chunk[0]);
+            chunk = &chunk[1..];
+
+            match std::str::from_utf8(&self.pending) {
+                Ok(_) => self.pending.clear(),
+                Err(e) if e.error_len().is_some() => return Err(e),
+                Err(_) => (),
+            }
         }
 
-        self.send_tx
-            .send_async(message)
-            .await
-            .map_err(|_| WebSocketError::ConnectionClosed)
+        match std::str::from_utf8(chunk) {
+            Ok(_) => (),
+            Err(e) if e.error_len().is_none() => {
+                self.pending.extend_from_slice(&chunk[e.valid_up_to()..])
+            }
+            Err(e) => return Err(e),
+        }
+
+        Ok(())
     }
 
-    async fn receive(&self) -> Result<Message, WebSocketError> {
-        self.receive_rx
-            .recv_async()
-            .await
-            .map_err(|_| WebSocketError::ConnectionClosed)
+    fn finish(&self) -> Result<(), std::str::Utf8Error> {
+        std::str::from_utf8(&self.pending).map(|_| ())
     }
+}
 
-    #[allow(unused_must_use)]
-    async fn stop(self) {
-        self.stop_tx.send(());
+fn validate_text_chunks(chunks: &[Bytes]) -> Result<(), std::str::Utf8Error> {
+    let mut utf8 = Utf8Validator::default();
 
-        self.handle.await;
+    for chunk in chunks {
+        utf8.feed(chunk)?;
     }
+
+    utf8.finish()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ControlKind {
+    Ping,
+    Pong,
 }
 
 #[derive(Debug)]
-struct Handler {
-    stream: TcpStream,
-    mask: Mask,
+pub struct ControlFrame {
+    pub(crate) kind: ControlKind,
+    pub(crate) payload: Binary,
+    pub(crate) received_at: Instant,
 }
 
-impl Handler {
-    async fn decode(&mut self) -> Result<RawFrame, WebSocketError> {
-        let octet = self.stream.read_u8().await?;
-        let fin = (octet >> 7) & 1 != 0;
-        if (octet >> 4) & 0b111 != 0 {
-            return Err(InvalidFrame::Inconsistent.into());
+impl ControlFrame {
+    pub fn kind(&self) -> ControlKind {
+        self.kind
+    }
+
+    pub fn payload(&self) -> &Binary {
+        &self.payload
+    }
+
+    pub fn received_at(&self) -> Instant {
+        self.received_at
+    }
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CloseReason {
+    pub(crate) code: StatusCode,
+    pub(crate) reason: Option<Text>,
+}
+
+impl CloseReason {
+    pub fn code(&self) -> StatusCode {
+        self.code
+    }
+
+    pub fn reason(&self) -> Option<&Text> {
+        self.reason.as_ref()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventKind {
+    HandshakeComplete,
+    IdleTimeout,
+    PingTimeout,
+    CloseStarted {
+        local: bool,
+        code: Option<StatusCode>,
+    },
+    CloseFinished {
+        code: Option<StatusCode>,
+    },
+    Error(ErrorKind),
+}
+
+#[derive(Debug)]
+pub struct Event {
+    pub(crate) kind: EventKind,
+    pub(crate) at: Instant,
+}
+
+impl Event {
+    pub fn kind(&self) -> EventKind {
+        self.kind
+    }
+
+    pub fn at(&self) -> Instant {
+        self.at
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum State {
+    Open,
+    ClosingLocal,
+    ClosingRemote,
+    Closed { code: Option<StatusCode> },
+}
+
+#[derive(Debug, Default)]
+struct SendState(AtomicU8);
+
+impl SendState {
+    const QUEUED: u8 = 0;
+    const CANCELLED: u8 = 1;
+    const TAKEN: u8 = 2;
+    const EXPIRED: u8 = 3;
+
+    fn transition(&self, to: u8) -> bool {
+        self.0
+            .compare_exchange(Self::QUEUED, to, Ordering::AcqRel, Ordering::Acquire)
+            .is_ok()
+    }
+
+    fn cancel(&self) -> bool {
+        self.transition(Self::CANCELLED)
+    }
+
+    fn take(&self) -> bool {
+        self.transition(Self::TAKEN)
+    }
+
+    fn expire(&self) -> bool {
+        self.transition(Self::EXPIRED)
+    }
+}
+
+#[derive(Debug)]
+enum Payload {
+    Message(Message),
+    Chunks(MessageKind, Vec<Bytes>),
+}
+
+impl Payload {
+    fn size(&self) -> usize {
+        match self {
+            Self::Message(message) => message.size(),
+            Self::Chunks(_, chunks) => chunks.iter().map(Bytes::len).sum(),
         }
-        let opcode: Opcode = (octet & 0xF).try_into()?;
-        match (fin, opcode) {
-            (false, Opcode::Ping | Opcode::Pong | Opcode::Close) | (true, Opcode::Continuation) => {
-                return Err(InvalidFrame::Inconsistent.into());
+    }
+
+    fn into_message(self) -> Message {
+        match self {
+            Self::Message(message) => message,
+            Self::Chunks(kind, chunks) => {
+                let payload = Bytes::from(chunks.concat());
+
+                match kind {
+                    MessageKind::Text => Message::Text(Text(payload)),
+                    MessageKind::Binary => Message::Binary(Binary(payload)),
+                }
             }
-            _ => (),
         }
+    }
+}
 
-        let octet = self.stream.read_u8().await?;
-        let masked = (octet >> 7) & 1 != 0;
-        match self.mask {
-            Mask::ClientSide if !masked => (),
-            Mask::ServerSide if masked => (),
-            _ => return Err(InvalidFrame::Inconsistent.into()),
-        }
-        let possible_payload_length = octet ^ (1 << 8);
-        let payload_length = match possible_payload_length {
-            (0..=125) => possible_payload_length as usize,
-            126 => self.stream.read_u16().await? as usize,
-            127 => self.stream.read_u64().await? as usize,
-            _ => return Err(InvalidFrame::Inconsistent.into()),
-        };
-        if payload_length > MAX_FRAME_PAYLOAD_SIZE {
-            return Err(InvalidFrame::PayloadSize.into());
+#[derive(Debug)]
+struct Activity {
+    started: Instant,
+    received: AtomicU64,
+    sent: AtomicU64,
+}
+
+impl Activity {
+    fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            received: AtomicU64::new(0),
+            sent: AtomicU64::new(0),
         }
+    }
 
-        let masking_key = if let Mask::ServerSide = self.mask {
-            Some(self.stream.read_u32().await?)
-        } else {
-            None
-        };
+    fn record(&self, at: &AtomicU64) {
+        let elapsed = self.started.elapsed().as_nanos() as u64;
 
-        let payload = if payload_length > 0 {
-            let mut payload = BytesMut::with_capacity(payload_length);
-            self.stream.read_exact(&mut payload).await?;
+        at.store(elapsed, Ordering::Relaxed);
+    }
 
-            if let Mask::ServerSide = self.mask {
-                xor_payload(masking_key.unwrap(), &mut payload);
-            }
+    fn record_received(&self) {
+        self.record(&self.received);
+    }
 
-            payload.into()
-        } else {
-            Bytes::new()
-        };
+    fn record_sent(&self) {
+        self.record(&self.sent);
+    }
 
-        let raw_frame = RawFrame {
-            fin,
-            opcode,
-            payload,
-        };
+    fn instant(&self, at: &AtomicU64) -> Instant {
+        self.started + Duration::from_nanos(at.load(Ordering::Relaxed))
+    }
 
-        Ok(raw_frame)
+    fn last_received(&self) -> Instant {
+        self.instant(&self.received)
     }
 
-    async fn encode(&mut self, raw_frame: RawFrame) -> Result<(), WebSocketError> {
-        let fin = if raw_frame.fin { 1 } else { 0 };
-        let opcode: u8 = raw_frame.opcode.into();
-        let octet = (fin << 8) | opcode;
-        self.stream.write_u8(octet).await?;
+    fn last_sent(&self) -> Instant {
+        self.instant(&self.sent)
+    }
+}
 
-        let masked = match self.mask {
-            Mask::ClientSide => 1,
-            Mask::ServerSide => 0,
-        };
-        let mut octet = masked << 8;
-        let payload_length = raw_frame.payload.len();
-        octet |= match payload_length {
-            (0..=125) => payload_length as u8,
-            (126..=0xFFFF) => 126,
-            _ => 127,
-        };
-        self.stream.write_u8(octet).await?;
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct QueueStats {
+    max_depth: usize,
+    time_above_watermark: Duration,
+}
 
-        match payload_length {
-            (0..=125) => (),
-            (126..=0xFFFF) => self.stream.write_u16(payload_length as u16).await?,
-            _ => self.stream.write_u64(payload_length as u64).await?,
-        }
+impl QueueStats {
+    pub fn max_depth(&self) -> usize {
+        self.max_depth
+    }
+
+    pub fn time_above_watermark(&self) -> Duration {
+        self.time_above_watermark
+    }
+}
+
+#[derive(Debug, Default)]
+struct Pressure {
+    watermark: Option<usize>,
+    max_depth: usize,
+    above_since: Option<Instant>,
+    time_above: Duration,
+}
+
+#[derive(Debug, Default)]
+struct QueuePressure(Mutex<Pressure>);
+
+impl QueuePressure {
+    fn record(&self, depth: usize) {
+        let mut pressure = self.0.lock().unwrap();
+
+        pressure.max_depth = pressure.max_depth.max(depth);
 
-        let mut payload: BytesMut = raw_frame.payload.into();
-        if let Mask::ClientSide = self.mask {
-            let masking_key = rand::random::<u32>();
-            self.stream.write_u32(masking_key).await?;
-            xor_payload(masking_key, &mut payload);
+        let above = pressure
+            .watermark
+            .is_some_and(|watermark| depth > watermark);
+        match (above, pressure.above_since) {
+            (true, None) => pressure.above_since = Some(Instant::now()),
+            (false, Some(since)) => {
+                pressure.time_above += since.elapsed();
+                pressure.above_since = None;
+            }
+            _ => (),
         }
-        if payload_length > 0 {
-            self.stream.write_all(&payload).await?;
+    }
+
+    fn set_watermark(&self, watermark: usize) {
+        let mut pressure = self.0.lock().unwrap();
+
+        if let Some(since) = pressure.above_since.take() {
+            pressure.time_above += since.elapsed();
         }
+        pressure.watermark = Some(watermark);
+    }
 
-        Ok(())
+    fn stats(&self) -> QueueStats {
+        let pressure = self.0.lock().unwrap();
+
+        let ongoing = pressure
+            .above_since
+            .map_or(Duration::ZERO, |since| since.elapsed());
+
+        QueueStats {
+            max_depth: pressure.max_depth,
+            time_above_watermark: pressure.time_above + ongoing,
+        }
     }
+}
 
-    fn start_handler(stream: TcpStream, mask: Mask) -> Controller {
-        let (send_tx, send_rx) = flume::unbounded();
-        let (receive_tx, receive_rx) = flume::unbounded();
-        let (stop_tx, stop_rx) = tokio::sync::oneshot::channel();
+#[derive(Debug)]
+struct Outgoing {
+    payload: Payload,
+    state: Arc<SendState>,
+    deadline: Option<Instant>,
+}
 
-        let handle = tokio::spawn(async move {
-            let mut manager = Handler { stream, mask };
+#[derive(Debug)]
+struct Scheduled {
+    at: Instant,
+    sequence: u64,
+    outgoing: Outgoing,
+}
 
-            loop {
-                tokio::select! {
-                    message = send_rx.recv_async() => {
-                        let _ = message;
+impl PartialEq for Scheduled {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == CmpOrdering::Equal
+    }
+}
 
-                        todo!()
-                    },
-                    raw_frame = manager.decode() => {
-                        let _ = raw_frame;
-                        let _ = receive_tx;
+impl Eq for Scheduled {}
 
-                        todo!()
-                    },
-                    _ = stop_rx => {
-                        todo!()
-                    },
-                }
-            }
-        });
+impl PartialOrd for Scheduled {
+    fn partial_cmp(&self, other: &Self) -> Option<CmpOrdering> {
+        Some(self.cmp(other))
+    }
+}
 
-        Controller {
-            send_tx,
-            receive_rx,
-            stop_tx,
-            handle,
-        }
+impl Ord for Scheduled {
+    fn cmp(&self, other: &Self) -> CmpOrdering {
+        (other.at, other.sequence).cmp(&(self.at, self.sequence))
     }
 }
 
-pub struct Connection {
-    controller: Controller,
+#[derive(Debug, Clone)]
+pub struct SendHandle {
+    state: Arc<SendState>,
 }
 
-impl Connection {
-    pub(crate) fn client_side(stream: TcpStream) -> Self {
-        let controller = Handler::start_handler(stream, Mask::ClientSide);
+impl SendHandle {
+    pub fn cancel(&self) -> bool {
+        self.state.cancel()
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.state.0.load(Ordering::Acquire) == SendState::CANCELLED
+    }
 
-        Self { controller }
+    pub fn is_expired(&self) -> bool {
+        self.state.0.load(Ordering::Acquire) == SendState::EXPIRED
     }
+}
+
+#[derive(Debug)]
+enum Command {
+    CloseSend(CloseContent),
+    ObserveControl(flume::Sender<ControlFrame>),
+    ObserveEvents(flume::Sender<Event>),
+    Ping(PingContent, Instant, PingReply),
+    Pong(PongContent),
+    Schedule(Instant, Outgoing),
+    SetLimits(Limits),
+    SetPingMode(PingMode),
+    SetFragmentSize(usize),
+    SetReceiving(bool),
+    Detach(tokio::sync::oneshot::Sender<Detached>),
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+struct PartialMessage {
+    kind: MessageKind,
+    payload: Vec<u8>,
+    fragments: usize,
+    wire_size: usize,
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+struct DelayedMessage {
+    delay: Duration,
+    message: Message,
+}
 
-    pub(crate) fn server_side(stream: TcpStream) -> Self {
-        let controller = Handler::start_handler(stream, Mask::ServerSide);
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ConnectionState {
+    role: Role,
+    path: String,
+    version: u16,
+    headers: Vec<(String, Vec<u8>)>,
+    buffered: Vec<u8>,
+    partial: Option<PartialMessage>,
+    pending: Vec<Envelope>,
+    scheduled: Vec<DelayedMessage>,
+    limits: Limits,
+    ping_mode: PingMode,
+    fragment_size: usize,
+}
 
-        Self { controller }
+impl ConnectionState {
+    pub fn role(&self) -> Role {
+        self.role
     }
 
-    pub async fn send(&self, message: Message) -> Result<(), WebSocketError> {
-        self.controller.send(message).await
+    pub fn pending_messages(&self) -> usize {
+        self.pending.len()
     }
 
-    pub async fn receive(&self) -> Result<Message, WebSocketError> {
-        self.controller.receive().await
+    pub fn scheduled_messages(&self) -> usize {
+        self.scheduled.len()
     }
 
-    pub async fn stop(self) {
-        self.controller.stop().await;
+    pub fn has_partial_message(&self) -> bool {
+        self.partial.is_some()
+    }
+}
+
+#[derive(Debug)]
+struct Detached {
+    stream: TcpStream,
+    buffered: Bytes,
+    fragments: Option<Fragments>,
+    scheduled: Vec<DelayedMessage>,
+    limits: Limits,
+    ping_mode: PingMode,
+    fragment_size: usize,
+}
+
+#[derive(Debug)]
+struct Session {
+    fragments: Option<Fragments>,
+    pending: Vec<Envelope>,
+    scheduled: Vec<DelayedMessage>,
+    limits: Limits,
+    ping_mode: PingMode,
+    fragment_size: usize,
+}
+
+impl Session {
+    fn new(config: &WebSocketConfig) -> Self {
+        Self {
+            fragments: None,
+            pending: Vec::new(),
+            scheduled: Vec::new(),
+            limits: Limits::default().with_max_message_size(config.max_message_size()),
+            ping_mode: PingMode::default(),
+            fragment_size: MAX_FRAME_PAYLOAD_SIZE,
+        }
+    }
+}
+
+type ExchangeHook = Arc<dyn Fn(&HttpExchange) + Send + Sync>;
+
+type Redaction = Arc<dyn Fn(&str) -> bool + Send + Sync>;
+
+#[derive(Clone, Default)]
+pub struct WebSocketConfig {
+    lenient_masking: bool,
+    compliance: Compliance,
+    strict_close_reason: bool,
+    raw_text_fragments: bool,
+    exchange_hook: Option<ExchangeHook>,
+    redaction: Option<Redaction>,
+    metadata: Vec<(String, String)>,
+    protocol_versions: Vec<u16>,
+    max_frame_size: Option<usize>,
+    max_message_size: Option<usize>,
+    max_fragments: Option<usize>,
+    work_budget: Option<usize>,
+}
+
+impl WebSocketConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_lenient_masking(mut self, lenient: bool) -> Self {
+        self.lenient_masking = lenient;
+
+        self
+    }
+
+    pub fn with_compliance(mut self, compliance: Compliance) -> Self {
+        self.compliance = compliance;
+
+        self
+    }
+
+    pub fn with_strict_close_reason(mut self, strict: bool) -> Self {
+        self.strict_close_reason = strict;
+
+        self
+    }
+
+    pub fn with_raw_text_fragments(mut self, raw: bool) -> Self {
+        self.raw_text_fragments = raw;
+
+        self
+    }
+
+    pub fn with_exchange_hook(
+        mut self,
+        hook: impl Fn(&HttpExchange) + Send + Sync + 'static,
+    ) -> Self {
+        self.exchange_hook = Some(Arc::new(hook));
+
+        self
+    }
+
+    pub fn with_redaction(mut self, redact: impl Fn(&str) -> bool + Send + Sync + 'static) -> Self {
+        self.redaction = Some(Arc::new(redact));
+
+        self
+    }
+
+    pub fn with_metadata(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.metadata.push((key.into(), value.into()));
+
+        self
+    }
+
+    pub fn with_protocol_versions(mut self, versions: impl IntoIterator<Item = u16>) -> Self {
+        self.protocol_versions = versions.into_iter().collect();
+
+        self
+    }
+
+    pub fn with_max_frame_size(mut self, size: usize) -> Self {
+        self.max_frame_size = Some(size);
+
+        self
+    }
+
+    pub fn with_max_message_size(mut self, size: usize) -> Self {
+        self.max_message_size = Some(size);
+
+        self
+    }
+
+    pub fn with_max_fragments(mut self, fragments: usize) -> Self {
+        self.max_fragments = Some(fragments);
+
+        self
+    }
+
+    pub fn with_work_budget(mut self, budget: usize) -> Self {
+        self.work_budget = Some(budget);
+
+        self
+    }
+
+    pub fn lenient_masking(&self) -> bool {
+        self.lenient_masking
+    }
+
+    pub fn compliance(&self) -> Compliance {
+        self.compliance
+    }
+
+    pub fn strict_close_reason(&self) -> bool {
+        self.strict_close_reason
+    }
+
+    pub fn raw_text_fragments(&self) -> bool {
+        self.raw_text_fragments
+    }
+
+    pub fn metadata(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.metadata
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+    }
+
+    pub fn max_frame_size(&self) -> usize {
+        self.max_frame_size.unwrap_or(MAX_FRAME_PAYLOAD_SIZE)
+    }
+
+    pub fn max_message_size(&self) -> usize {
+        self.max_message_size.unwrap_or(MAX_MESSAGE_SIZE)
+    }
+
+    pub fn max_fragments(&self) -> Option<usize> {
+        self.max_fragments
+    }
+
+    pub fn work_budget(&self) -> usize {
+        self.work_budget.unwrap_or(WORK_BUDGET).max(1)
+    }
+
+    pub fn protocol_versions(&self) -> &[u16] {
+        if self.protocol_versions.is_empty() {
+            return &[PROTOCOL_VERSION];
+        }
+
+        &self.protocol_versions
+    }
+
+    pub(crate) fn metadata_header(&self) -> Option<Bytes> {
+        if self.metadata.is_empty() {
+            return None;
+        }
+
+        let header = self
+            .metadata
+            .iter()
+            .map(|(key, value)| format!("{}={}", percent_encode(key), percent_encode(value)))
+            .collect::<Vec<_>>()
+            .join("; ");
+
+        Some(header.into())
+    }
+
+    pub(crate) fn log_exchange(&self, request: &[u8], response: &[u8]) {
+        let Some(hook) = &self.exchange_hook else {
+            return;
+        };
+
+        let redact = |name: &str| match &self.redaction {
+            Some(redaction) => redaction(name),
+            None => is_sensitive_header(name),
+        };
+        let exchange = HttpExchange {
+            request: redact_raw_http(request, redact),
+            response: redact_raw_http(response, redact),
+        };
+
+        hook(&exchange);
+    }
+}
+
+impl std::fmt::Debug for WebSocketConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WebSocketConfig")
+            .field("lenient_masking", &self.lenient_masking)
+            .field("compliance", &self.compliance)
+            .field("strict_close_reason", &self.strict_close_reason)
+            .field("raw_text_fragments", &self.raw_text_fragments)
+            .field("exchange_hook", &self.exchange_hook.is_some())
+            .field("redaction", &self.redaction.is_some())
+            .field("metadata", &self.metadata)
+            .field("protocol_versions", &self.protocol_versions())
+            .field("max_frame_size", &self.max_frame_size())
+            .field("max_message_size", &self.max_message_size())
+            .field("max_fragments", &self.max_fragments)
+            .field("work_budget", &self.work_budget())
+            .finish()
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum PingMode {
+    #[default]
+    Automatic,
+    Manual,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum RatePolicy {
+    #[default]
+    Close,
+    Throttle,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Compliance {
+    #[default]
+    Strict,
+    Lenient,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Limits {
+    max_message_size: usize,
+    rate_limit: Option<(u32, Duration)>,
+    frame_rate_limit: Option<(u32, Duration)>,
+    byte_rate_limit: Option<(u64, Duration)>,
+    control_rate_limit: Option<(u32, Duration)>,
+    rate_policy: RatePolicy,
+    idle_timeout: Option<Duration>,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_message_size: MAX_MESSAGE_SIZE,
+            rate_limit: None,
+            frame_rate_limit: None,
+            byte_rate_limit: None,
+            control_rate_limit: None,
+            rate_policy: RatePolicy::default(),
+            idle_timeout: None,
+        }
+    }
+}
+
+impl Limits {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_max_message_size(mut self, size: usize) -> Self {
+        self.max_message_size = size;
+
+        self
+    }
+
+    pub fn with_rate_limit(mut self, messages: u32, per: Duration) -> Self {
+        self.rate_limit = Some((messages, per));
+
+        self
+    }
+
+    pub fn with_frame_rate_limit(mut self, frames: u32, per: Duration) -> Self {
+        self.frame_rate_limit = Some((frames, per));
+
+        self
+    }
+
+    pub fn with_byte_rate_limit(mut self, bytes: u64, per: Duration) -> Self {
+        self.byte_rate_limit = Some((bytes, per));
+
+        self
+    }
+
+    pub fn with_control_rate_limit(mut self, frames: u32, per: Duration) -> Self {
+        self.control_rate_limit = Some((frames, per));
+
+        self
+    }
+
+    pub fn with_rate_policy(mut self, policy: RatePolicy) -> Self {
+        self.rate_policy = policy;
+
+        self
+    }
+
+    pub fn with_idle_timeout(mut self, timeout: Duration) -> Self {
+        self.idle_timeout = Some(timeout);
+
+        self
+    }
+
+    pub fn max_message_size(&self) -> usize {
+        self.max_message_size
+    }
+
+    pub fn rate_limit(&self) -> Option<(u32, Duration)> {
+        self.rate_limit
+    }
+
+    pub fn frame_rate_limit(&self) -> Option<(u32, Duration)> {
+        self.frame_rate_limit
+    }
+
+    pub fn byte_rate_limit(&self) -> Option<(u64, Duration)> {
+        self.byte_rate_limit
+    }
+
+    pub fn control_rate_limit(&self) -> Option<(u32, Duration)> {
+        self.control_rate_limit
+    }
+
+    pub fn rate_policy(&self) -> RatePolicy {
+        self.rate_policy
+    }
+
+    pub fn idle_timeout(&self) -> Option<Duration> {
+        self.idle_timeout
+    }
+}
+
+#[derive(Debug)]
+struct Window {
+    start: Instant,
+    count: u64,
+}
+
+impl Window {
+    fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            count: 0,
+        }
+    }
+
+    fn record(&mut self, amount: u64, (limit, per): (u64, Duration)) -> Option<Instant> {
+        let now = Instant::now();
+        if now.duration_since(self.start) >= per {
+            self.start = now;
+            self.count = 0;
+        }
+        self.count += amount;
+
+        (self.count > limit).then_some(self.start + per)
+    }
+}
+
+#[derive(Debug)]
+struct Windows {
+    messages: Window,
+    frames: Window,
+    bytes: Window,
+    control: Window,
+}
+
+impl Windows {
+    fn new() -> Self {
+        Self {
+            messages: Window::new(),
+            frames: Window::new(),
+            bytes: Window::new(),
+            control: Window::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DropPolicy {
+    #[default]
+    Close,
+    Abort,
+    Leak,
+}
+
+#[derive(Debug)]
+struct AbortOnDrop<T>(tokio::task::JoinHandle<T>);
+
+impl<T> Drop for AbortOnDrop<T> {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+struct Controller {
+    send_tx: flume::Sender<Outgoing>,
+    receive_rx: flume::Receiver<Result<Envelope, WebSocketError>>,
+    command_tx: flume::Sender<Command>,
+    state_rx: tokio::sync::watch::Receiver<State>,
+    stop_tx: Option<tokio::sync::oneshot::Sender<()>>,
+    handle: Option<tokio::task::JoinHandle<()>>,
+    drop_policy: DropPolicy,
+    limits: Limits,
+    activity: Arc<Activity>,
+    close_reason: Arc<OnceLock<CloseReason>>,
+    queue_pressure: Arc<QueuePressure>,
+    size_check_tx: tokio::sync::watch::Sender<Option<SizeCheck>>,
+    role: Role,
+    config: WebSocketConfig,
+}
+
+impl Controller {
+    fn prepare(&self, payload: Payload) -> Result<(Outgoing, SendHandle), WebSocketError> {
+        if payload.size() > self.limits.max_message_size {
+            return Err(WebSocketError::InvalidMessageSize);
+        }
+
+        if let Payload::Chunks(MessageKind::Text, chunks) = &payload {
+            validate_text_chunks(chunks).map_err(InvalidFrame::from)?;
+        }
+
+        let state = Arc::new(SendState::default());
+        let outgoing = Outgoing {
+            payload,
+            state: state.clone(),
+            deadline: None,
+        };
+
+        Ok((outgoing, SendHandle { state }))
+    }
+
+    async fn send(
+        &self,
+        payload: Payload,
+        deadline: Option<Instant>,
+    ) -> Result<SendHandle, WebSocketError> {
+        let (mut outgoing, handle) = self.prepare(payload)?;
+        outgoing.deadline = deadline;
+
+        self.send_tx
+            .send_async(outgoing)
+            .await
+            .map_err(|_| WebSocketError::ConnectionClosed)?;
+        self.queue_pressure.record(self.send_tx.len());
+
+        Ok(handle)
+    }
+
+    async fn send_at(&self, message: Message, at: Instant) -> Result<SendHandle, WebSocketError> {
+        let (outgoing, handle) = self.prepare(Payload::Message(message))?;
+
+        self.command_tx
+            .send_async(Command::Schedule(at, outgoing))
+            .await
+            .map_err(|_| WebSocketError::ConnectionClosed)?;
+
+        Ok(handle)
+    }
+
+    async fn receive(&self) -> Result<Envelope, WebSocketError> {
+        self.receive_rx
+            .recv_async()
+            .await
+            .map_err(|_| WebSocketError::ConnectionClosed)?
+    }
+
+    async fn close_send(
+        &self,
+        status: StatusCode,
+        reason: Option<Text>,
+    ) -> Result<(), WebSocketError> {
+        if !status.is_valid() {
+            return Err(InvalidFrame::Code(status.into()).into());
+        }
+
+        let size = 2 + reason.as_ref().map_or(0, |reason| reason.len());
+        if size > MAX_CONTROL_PAYLOAD_SIZE && self.config.strict_close_reason {
+            return Err(InvalidFrame::ControlTooLarge {
+                opcode: Opcode::Close.into(),
+                len: size,
+            }
+            .into());
+        }
+        let reason = reason.map(|reason| truncate_reason(reason, MAX_CONTROL_PAYLOAD_SIZE - 2));
+
+        let content = CloseContent { status, reason };
+
+        self.command_tx
+            .send_async(Command::CloseSend(content))
+            .await
+            .map_err(|_| WebSocketError::ConnectionClosed)
+    }
+
+    async fn close(
+        &self,
+        status: StatusCode,
+        reason: Option<Text>,
+    ) -> Result<Option<StatusCode>, WebSocketError> {
+        self.close_send(status, reason).await?;
+
+        let mut state_rx = self.state_rx.clone();
+        let closed = state_rx
+            .wait_for(|state| matches!(state, State::Closed { .. }))
+            .await;
+
+        match closed.as_deref() {
+            Ok(State::Closed { code }) => Ok(*code),
+            _ => Ok(None),
+        }
+    }
+
+    async fn ping(&self, payload: Binary, timeout: Duration) -> Result<Duration, WebSocketError> {
+        if payload.len() > MAX_CONTROL_PAYLOAD_SIZE {
+            return Err(InvalidFrame::ControlTooLarge {
+                opcode: Opcode::Ping.into(),
+                len: payload.len(),
+            }
+            .into());
+        }
+
+        let (reply_tx, reply_rx) = tokio::sync::oneshot::channel();
+        let expires = Instant::now() + timeout;
+
+        self.command_tx
+            .send_async(Command::Ping(payload, expires, reply_tx))
+            .await
+            .map_err(|_| WebSocketError::ConnectionClosed)?;
+
+        reply_rx
+            .await
+            .map_err(|_| WebSocketError::ConnectionClosed)?
+    }
+
+    async fn pong(&self, payload: Binary) -> Result<(), WebSocketError> {
+        if payload.len() > MAX_CONTROL_PAYLOAD_SIZE {
+            return Err(InvalidFrame::ControlTooLarge {
+                opcode: Opcode::Pong.into(),
+                len: payload.len(),
+            }
+            .into());
+        }
+
+        self.command_tx
+            .send_async(Command::Pong(payload))
+            .await
+            .map_err(|_| WebSocketError::ConnectionClosed)
+    }
+
+    async fn observe_control_frames(
+        &self,
+    ) -> Result<flume::Receiver<ControlFrame>, WebSocketError> {
+        let (control_tx, control_rx) = flume::unbounded();
+
+        self.command_tx
+            .send_async(Command::ObserveControl(control_tx))
+            .await
+            .map_err(|_| WebSocketError::ConnectionClosed)?;
+
+        Ok(control_rx)
+    }
+
+    async fn observe_events(&self) -> Result<flume::Receiver<Event>, WebSocketError> {
+        let (events_tx, events_rx) = flume::unbounded();
+
+        self.command_tx
+            .send_async(Command::ObserveEvents(events_tx))
+            .await
+            .map_err(|_| WebSocketError::ConnectionClosed)?;
+
+        Ok(events_rx)
+    }
+
+    fn state(&self) -> State {
+        *self.state_rx.borrow()
+    }
+
+    fn set_fragment_size(&self, fragment_size: usize) {
+        let fragment_size = fragment_size.clamp(MIN_FRAGMENT_SIZE, MAX_FRAME_PAYLOAD_SIZE);
+
+        let _ = self
+            .command_tx
+            .send(Command::SetFragmentSize(fragment_size));
+    }
+
+    fn set_receiving(&self, receiving: bool) {
+        let _ = self.command_tx.send(Command::SetReceiving(receiving));
+    }
+
+    fn set_ping_mode(&self, ping_mode: PingMode) {
+        let _ = self.command_tx.send(Command::SetPingMode(ping_mode));
+    }
+
+    fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+
+        let _ = self.command_tx.send(Command::SetLimits(limits));
+    }
+
+    async fn detach(mut self) -> Result<(Detached, Vec<Envelope>), WebSocketError> {
+        let (reply_tx, reply_rx) = tokio::sync::oneshot::channel();
+
+        self.command_tx
+            .send_async(Command::Detach(reply_tx))
+            .await
+            .map_err(|_| WebSocketError::ConnectionClosed)?;
+        let detached = reply_rx
+            .await
+            .map_err(|_| WebSocketError::ConnectionClosed)?;
+
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.await;
+        }
+
+        let pending = self.receive_rx.drain().filter_map(Result::ok).collect();
+
+        Ok((detached, pending))
+    }
+
+    #[allow(unused_must_use)]
+    async fn stop(mut self) {
+        if let Some(stop_tx) = self.stop_tx.take() {
+            stop_tx.send(());
+        }
+
+        if let Some(handle) = self.handle.take() {
+            handle.await;
+        }
+    }
+}
+
+impl Drop for Controller {
+    fn drop(&mut self) {
+        let Some(handle) = self.handle.take() else {
+            return;
+        };
+
+        match self.drop_policy {
+            DropPolicy::Close => {
+                let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+                    handle.abort();
+
+                    return;
+                };
+
+                let content = CloseContent {
+                    status: StatusCode::GoingAway,
+                    reason: None,
+                };
+                let _ = self.command_tx.send(Command::CloseSend(content));
+
+                let abort_handle = handle.abort_handle();
+                runtime.spawn(async move {
+                    if tokio::time::timeout(CLOSE_TIMEOUT, handle).await.is_err() {
+                        abort_handle.abort();
+                    }
+                });
+            }
+            DropPolicy::Abort => handle.abort(),
+            DropPolicy::Leak => {
+                log::warn!("connection dropped without being stopped, its handler keeps running");
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Prefixed {
+    buffered: Bytes,
+    stream: OwnedReadHalf,
+}
+
+impl Prefixed {
+    fn new(buffered: Bytes, stream: OwnedReadHalf) -> Self {
+        Self { buffered, stream }
+    }
+}
+
+impl AsyncRead for Prefixed {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        if self.buffered.is_empty() {
+            return Pin::new(&mut self.stream).poll_read(cx, buf);
+        }
+
+        let length = self.buffered.len().min(buf.remaining());
+        buf.put_slice(&self.buffered.split_to(length));
+
+        Poll::Ready(Ok(()))
+    }
+}
+
+type SizeCheck = Arc<dyn Fn(usize) -> bool + Send + Sync>;
+
+struct Reader {
+    stream: Prefixed,
+    mask: Mask,
+    lenient_masking: bool,
+    warned_unmasked: bool,
+    compliance: Compliance,
+    max_frame_size: usize,
+    size_check: tokio::sync::watch::Receiver<Option<SizeCheck>>,
+}
+
+impl Reader {
+    async fn decode(&mut self, octet: u8) -> Result<ReceivedFrame, WebSocketError> {
+        let fin = (octet >> 7) & 1 != 0;
+        if (octet >> 4) & 0b111 != 0 && self.compliance == Compliance::Strict {
+            return Err(InvalidFrame::Inconsistent.into());
+        }
+        let opcode: Opcode = (octet & 0xF).try_into()?;
+        if !fin && matches!(opcode, Opcode::Ping | Opcode::Pong | Opcode::Close) {
+            return Err(InvalidFrame::Inconsistent.into());
+        }
+
+        let octet = self.stream.read_u8().await?;
+        let masked = (octet >> 7) & 1 != 0;
+        match (self.mask, masked) {
+            (Mask::ClientSide, false) | (Mask::ServerSide, true) => (),
+            (Mask::ServerSide, false) if self.lenient_masking => {
+                if !self.warned_unmasked {
+                    log::warn!("accepting unmasked frames from client");
+                    self.warned_unmasked = true;
+                }
+            }
+            _ => return Err(InvalidFrame::Inconsistent.into()),
+        }
+        let possible_payload_length = octet & 0x7F;
+        let (payload_length, header_size) = match possible_payload_length {
+            (0..=125) => (possible_payload_length as usize, 2),
+            126 => (self.stream.read_u16().await? as usize, 4),
+            127 => (self.stream.read_u64().await? as usize, 10),
+            _ => return Err(InvalidFrame::Inconsistent.into()),
+        };
+        if payload_length > self.max_frame_size {
+            return Err(InvalidFrame::PayloadSize.into());
+        }
+        if let Opcode::Ping | Opcode::Pong | Opcode::Close = opcode
+            && payload_length > MAX_CONTROL_PAYLOAD_SIZE
+        {
+            return Err(InvalidFrame::ControlTooLarge {
+                opcode: opcode.into(),
+                len: payload_length,
+            }
+            .into());
+        }
+        if let Opcode::Continuation | Opcode::Text | Opcode::Binary = opcode
+            && let Some(size_check) = &*self.size_check.borrow()
+            && !size_check(payload_length)
+        {
+            return Err(InvalidFrame::PayloadRejected(payload_length).into());
+        }
+
+        let masking_key = if masked {
+            Some(self.stream.read_u32().await?)
+        } else {
+            None
+        };
+        let wire_size = header_size + masking_key.map_or(0, |_| 4) + payload_length;
+
+        let payload = if payload_length > 0 {
+            let mut payload = BytesMut::with_capacity(payload_length);
+            while payload.len() < payload_length {
+                let remaining = (payload_length - payload.len()) as u64;
+                let read = (&mut self.stream)
+                    .take(remaining)
+                    .read_buf(&mut payload)
+                    .await?;
+                if read == 0 {
+                    return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
+                }
+            }
+
+            if let Some(masking_key) = masking_key {
+                xor_payload(masking_key, &mut payload);
+            }
+
+            payload.into()
+        } else {
+            Bytes::new()
+        };
+
+        let raw_frame = RawFrame {
+            fin,
+            opcode,
+            payload,
+        };
+
+        Ok(ReceivedFrame {
+            raw_frame,
+            wire_size,
+        })
+    }
+}
+
+impl Reader {
+    fn start_reader(
+        stream: Prefixed,
+        mask: Mask,
+        lenient_masking: bool,
+        compliance: Compliance,
+        max_frame_size: usize,
+        size_check: tokio::sync::watch::Receiver<Option<SizeCheck>>,
+        mut detach_rx: tokio::sync::oneshot::Receiver<()>,
+    ) -> (
+        flume::Receiver<Result<ReceivedFrame, WebSocketError>>,
+        tokio::task::JoinHandle<Option<Prefixed>>,
+    ) {
+        let (frame_tx, frame_rx) = flume::bounded(1);
+
+        let handle = tokio::spawn(async move {
+            let mut reader = Reader {
+                stream,
+                mask,
+                lenient_masking,
+                warned_unmasked: false,
+                compliance,
+                max_frame_size,
+                size_check,
+            };
+
+            loop {
+                let octet = tokio::select! {
+                    biased;
+
+                    Ok(()) = &mut detach_rx => return Some(reader.stream),
+                    octet = reader.stream.read_u8() => octet,
+                };
+                let raw_frame = match octet {
+                    Ok(octet) => reader.decode(octet).await,
+                    Err(e) => Err(e.into()),
+                };
+                let failed = raw_frame.is_err();
+
+                if frame_tx.send_async(raw_frame).await.is_err() || failed {
+                    return None;
+                }
+            }
+        });
+
+        (frame_rx, handle)
+    }
+}
+
+#[derive(Debug)]
+struct Fragments {
+    kind: MessageKind,
+    payload: BytesMut,
+    utf8: Utf8Validator,
+    count: usize,
+    wire_size: usize,
+}
+
+impl Fragments {
+    fn new(kind: MessageKind, count: usize, wire_size: usize) -> Self {
+        Self {
+            kind,
+            payload: BytesMut::new(),
+            utf8: Utf8Validator::default(),
+            count,
+            wire_size,
+        }
+    }
+
+    fn append(&mut self, chunk: &[u8], fin: bool) -> Result<(), std::str::Utf8Error> {
+        if self.kind == MessageKind::Text {
+            self.utf8.feed(chunk)?;
+            if fin {
+                self.utf8.finish()?;
+            }
+        }
+        self.payload.put_slice(chunk);
+
+        Ok(())
+    }
+}
+
+type PingReply = tokio::sync::oneshot::Sender<Result<Duration, WebSocketError>>;
+
+#[derive(Debug)]
+struct PendingPing {
+    payload: Bytes,
+    sent: Instant,
+    expires: Instant,
+    reply_tx: PingReply,
+}
+
+#[derive(Debug)]
+struct Handler {
+    stream: BufWriter<OwnedWriteHalf>,
+    mask: Mask,
+    receive_tx: flume::Sender<Result<Envelope, WebSocketError>>,
+    control_tx: Option<flume::Sender<ControlFrame>>,
+    events_tx: Option<flume::Sender<Event>>,
+    state_tx: tokio::sync::watch::Sender<State>,
+    scheduled: BinaryHeap<Scheduled>,
+    scheduled_sequence: u64,
+    limits: Limits,
+    ping_mode: PingMode,
+    fragment_size: usize,
+    raw_text_fragments: bool,
+    fragments: Option<Fragments>,
+    discarding: bool,
+    max_fragments: Option<usize>,
+    compliance: Compliance,
+    close_deadline: Option<Instant>,
+    pings: Vec<PendingPing>,
+    activity: Arc<Activity>,
+    close_reason: Arc<OnceLock<CloseReason>>,
+    queue_pressure: Arc<QueuePressure>,
+    windows: Windows,
+    throttled_until: Option<Instant>,
+    paused: bool,
+    resumed_at: Option<Instant>,
+}
+
+impl Handler {
+    async fn write_masked(
+        &mut self,
+        masking_key: u32,
+        payload: &[u8],
+    ) -> Result<(), WebSocketError> {
+        let masking_key = masking_key.to_be_bytes();
+        let mut chunk = [0; MASKING_CHUNK_SIZE];
+
+        for part in payload.chunks(MASKING_CHUNK_SIZE) {
+            chunk
+                .iter_mut()
+                .zip(part)
+                .zip(masking_key.iter().cycle())
+                .for_each(|((masked, b), k)| *masked = b ^ k);
+            self.stream.write_all(&chunk[..part.len()]).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn encode(&mut self, raw_frame: RawFrame) -> Result<(), WebSocketError> {
+        let masking_key = match self.mask {
+            Mask::ClientSide => Some(rand::random::<u32>()),
+            Mask::ServerSide => None,
+        };
+        let payload_length = raw_frame.payload.len();
+        let mut header = BytesMut::with_capacity(MAX_FRAME_HEADER_SIZE);
+        put_frame_header(
+            &mut header,
+            raw_frame.fin,
+            raw_frame.opcode,
+            payload_length,
+            masking_key,
+        );
+        self.stream.write_all(&header).await?;
+
+        match masking_key {
+            Some(masking_key) if payload_length > 0 => {
+                self.write_masked(masking_key, &raw_frame.payload).await?;
+            }
+            None if payload_length > 0 => {
+                self.stream.write_all(&raw_frame.payload).await?;
+            }
+            _ => (),
+        }
+        self.stream.flush().await?;
+
+        self.activity.record_sent();
+
+        Ok(())
+    }
+
+    async fn close_send(&mut self, content: CloseContent) -> Result<(), WebSocketError> {
+        self.emit(EventKind::CloseStarted {
+            local: true,
+            code: Some(content.status),
+        });
+
+        let raw_frame = RawFrame {
+            fin: true,
+            opcode: Opcode::Close,
+            payload: content.into(),
+        };
+        self.encode(raw_frame).await?;
+
+        self.set_state(State::ClosingLocal);
+        self.close_deadline = Some(Instant::now() + CLOSE_TIMEOUT);
+        self.paused = false;
+
+        Ok(())
+    }
+
+    fn set_state(&self, state: State) {
+        self.state_tx.send_replace(state);
+    }
+
+    fn can_send(&self) -> bool {
+        matches!(*self.state_tx.borrow(), State::Open)
+    }
+
+    async fn send(&mut self, outgoing: Outgoing) -> Result<(), WebSocketError> {
+        if let Some(deadline) = outgoing.deadline
+            && deadline <= Instant::now()
+        {
+            outgoing.state.expire();
+
+            return Ok(());
+        }
+
+        if outgoing.state.take() {
+            self.send_payload(outgoing.payload).await?;
+        }
+
+        Ok(())
+    }
+
+    fn fragment(&self, kind: MessageKind, mut payload: Bytes) -> Vec<Bytes> {
+        let mut fragments = Vec::with_capacity(payload.len().div_ceil(self.fragment_size));
+
+        while payload.len() > self.fragment_size {
+            let mut at = self.fragment_size;
+            if kind == MessageKind::Text && !self.raw_text_fragments {
+                while at > 0 && (payload[at] as i8) < -0x40 {
+                    at -= 1;
+                }
+            }
+
+            fragments.push(payload.split_to(at));
+        }
+        fragments.push(payload);
+
+        fragments
+    }
+
+    async fn send_payload(&mut self, payload: Payload) -> Result<(), WebSocketError> {
+        let payload = match payload {
+            Payload::Message(Message::Text(text)) if text.len() > self.fragment_size => {
+                let chunks = self.fragment(MessageKind::Text, text.0);
+
+                Payload::Chunks(MessageKind::Text, chunks)
+            }
+            Payload::Message(Message::Binary(binary)) if binary.len() > self.fragment_size => {
+                let chunks = self.fragment(MessageKind::Binary, binary.0);
+
+                Payload::Chunks(MessageKind::Binary, chunks)
+            }
+            payload => payload,
+        };
+
+        let raw_frame = match payload {
+            Payload::Message(Message::Text(text)) => RawFrame {
+                fin: true,
+                opcode: Opcode::Text,
+                payload: text.0,
+            },
+            Payload::Message(Message::Binary(binary)) => RawFrame {
+                fin: true,
+                opcode: Opcode::Binary,
+                payload: binary.0,
+            },
+            Payload::Chunks(kind, chunks) => return self.send_chunks(kind, chunks).await,
+        };
+
+        self.encode(raw_frame).await
+    }
+
+    async fn send_chunks(
+        &mut self,
+        kind: MessageKind,
+        chunks: Vec<Bytes>,
+    ) -> Result<(), WebSocketError> {
+        if chunks.is_empty() {
+            let raw_frame = RawFrame {
+                fin: true,
+                opcode: kind.into(),
+                payload: Bytes::new(),
+            };
+
+            return self.encode(raw_frame).await;
+        }
+
+        let last = chunks.len() - 1;
+        for (i, payload) in chunks.into_iter().enumerate() {
+            let opcode = if i == 0 {
+                kind.into()
+            } else {
+                Opcode::Continuation
+            };
+            let raw_frame = RawFrame {
+                fin: i == last,
+                opcode,
+                payload,
+            };
+
+            self.encode(raw_frame).await?;
+        }
+
+        Ok(())
+    }
+
+    fn schedule(&mut self, at: Instant, outgoing: Outgoing) {
+        let sequence = self.scheduled_sequence;
+        self.scheduled_sequence += 1;
+
+        self.scheduled.push(Scheduled {
+            at,
+            sequence,
+            outgoing,
+        });
+    }
+
+    fn next_deadline(&self) -> Option<Instant> {
+        self.scheduled.peek().map(|scheduled| scheduled.at)
+    }
+
+    async fn send_scheduled(&mut self) -> Result<(), WebSocketError> {
+        let now = Instant::now();
+
+        while self.next_deadline().is_some_and(|at| at <= now) {
+            let scheduled = self.scheduled.pop().unwrap();
+
+            if self.can_send() {
+                self.send(scheduled.outgoing).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn ping(
+        &mut self,
+        content: PingContent,
+        expires: Instant,
+        reply_tx: PingReply,
+    ) -> Result<(), WebSocketError> {
+        let payload = content.0;
+        let raw_frame = RawFrame {
+            fin: true,
+            opcode: Opcode::Ping,
+            payload: payload.clone(),
+        };
+
+        self.encode(raw_frame).await?;
+
+        self.pings.retain(|ping| !ping.reply_tx.is_closed());
+        self.pings.push(PendingPing {
+            payload,
+            sent: Instant::now(),
+            expires,
+            reply_tx,
+        });
+
+        Ok(())
+    }
+
+    fn ping_deadline(&self) -> Option<Instant> {
+        self.pings.iter().map(|ping| ping.expires).min()
+    }
+
+    fn expire_pings(&mut self) {
+        let now = Instant::now();
+        let (expired, pending): (Vec<_>, _) = std::mem::take(&mut self.pings)
+            .into_iter()
+            .partition(|ping| ping.expires <= now);
+        self.pings = pending;
+
+        for ping in expired {
+            let _ = ping.reply_tx.send(Err(WebSocketError::PingTimeout));
+            self.emit(EventKind::PingTimeout);
+        }
+    }
+
+    fn resolve_ping(&mut self, payload: &Bytes) {
+        let Some(position) = self.pings.iter().position(|ping| ping.payload == *payload) else {
+            return;
+        };
+
+        let ping = self.pings.remove(position);
+        let _ = ping.reply_tx.send(Ok(ping.sent.elapsed()));
+    }
+
+    async fn pong(&mut self, content: PongContent) -> Result<(), WebSocketError> {
+        let raw_frame = RawFrame {
+            fin: true,
+            opcode: Opcode::Pong,
+            payload: content.0,
+        };
+
+        self.encode(raw_frame).await
+    }
+
+    async fn command(&mut self, command: Command) -> Result<(), WebSocketError> {
+        match command {
+            Command::CloseSend(_) | Command::Ping(..) | Command::Pong(_) if !self.can_send() => {
+                Ok(())
+            }
+            Command::CloseSend(content) => self.close_send(content).await,
+            Command::Ping(content, expires, reply_tx) => {
+                self.ping(content, expires, reply_tx).await
+            }
+            Command::Pong(content) => self.pong(content).await,
+            Command::Schedule(at, outgoing) => {
+                self.schedule(at, outgoing);
+
+                Ok(())
+            }
+            Command::ObserveControl(control_tx) => {
+                self.control_tx = Some(control_tx);
+
+                Ok(())
+            }
+            Command::ObserveEvents(events_tx) => {
+                let event = Event {
+                    kind: EventKind::HandshakeComplete,
+                    at: self.activity.started,
+                };
+
+                if events_tx.send(event).is_ok() {
+                    self.events_tx = Some(events_tx);
+                }
+
+                Ok(())
+            }
+            Command::SetLimits(limits) => {
+                self.limits = limits;
+
+                Ok(())
+            }
+            Command::SetPingMode(ping_mode) => {
+                self.ping_mode = ping_mode;
+
+                Ok(())
+            }
+            Command::SetFragmentSize(fragment_size) => {
+                self.fragment_size = fragment_size;
+
+                Ok(())
+            }
+            Command::SetReceiving(receiving) => {
+                if self.paused && receiving {
+                    self.resumed_at = Some(Instant::now());
+                }
+                self.paused = !receiving;
+
+                Ok(())
+            }
+            Command::Detach(_) => Ok(()),
+        }
+    }
+
+    async fn flush_and_close(
+        &mut self,
+        send_rx: &flume::Receiver<Outgoing>,
+        content: CloseContent,
+    ) -> Result<(), WebSocketError> {
+        while self.can_send() {
+            let Ok(outgoing) = send_rx.try_recv() else {
+                break;
+            };
+
+            self.send(outgoing).await?;
+        }
+
+        self.command(Command::CloseSend(content)).await
+    }
+
+    fn emit(&mut self, kind: EventKind) {
+        let Some(events_tx) = &self.events_tx else {
+            return;
+        };
+
+        let event = Event {
+            kind,
+            at: Instant::now(),
+        };
+
+        if events_tx.send(event).is_err() {
+            self.events_tx = None;
+        }
+    }
+
+    fn observe_control(&mut self, raw_frame: RawFrame) {
+        let Some(control_tx) = &self.control_tx else {
+            return;
+        };
+
+        let kind = match raw_frame.opcode {
+            Opcode::Ping => ControlKind::Ping,
+            _ => ControlKind::Pong,
+        };
+        let control_frame = ControlFrame {
+            kind,
+            payload: Binary(raw_frame.payload),
+            received_at: Instant::now(),
+        };
+
+        if control_tx.send(control_frame).is_err() {
+            self.control_tx = None;
+        }
+    }
+
+    async fn reply_close(&mut self, status: Option<StatusCode>) -> Result<(), WebSocketError> {
+        if self.can_send() {
+            self.set_state(State::ClosingRemote);
+            self.emit(EventKind::CloseStarted {
+                local: false,
+                code: status,
+            });
+
+            let payload = status.map_or_else(Bytes::new, |status| {
+                CloseContent {
+                    status,
+                    reason: None,
+                }
+                .into()
+            });
+            let raw_frame = RawFrame {
+                fin: true,
+                opcode: Opcode::Close,
+                payload,
+            };
+            self.encode(raw_frame).await?;
+        }
+
+        self.stream.shutdown().await?;
+
+        Ok(())
+    }
+
+    async fn drain(
+        &mut self,
+        detach_tx: tokio::sync::oneshot::Sender<()>,
+        reader: &mut tokio::task::JoinHandle<Option<Prefixed>>,
+        frame_rx: &flume::Receiver<Result<ReceivedFrame, WebSocketError>>,
+        send_rx: &flume::Receiver<Outgoing>,
+    ) -> Option<Prefixed> {
+        let _ = detach_tx.send(());
+
+        let prefixed = loop {
+            tokio::select! {
+                prefixed = &mut *reader => break prefixed.ok().flatten()?,
+                Ok(raw_frame) = frame_rx.recv_async() => self.absorb(raw_frame).await?,
+            }
+        };
+        while let Ok(raw_frame) = frame_rx.try_recv() {
+            self.absorb(raw_frame).await?;
+        }
+
+        while let Ok(outgoing) = send_rx.try_recv() {
+            self.send(outgoing).await.ok()?;
+        }
+        self.stream.flush().await.ok()?;
+
+        Some(prefixed)
+    }
+
+    fn hand_over(&mut self) -> Vec<DelayedMessage> {
+        for ping in self.pings.drain(..) {
+            let _ = ping.reply_tx.send(Err(WebSocketError::HandedOver));
+        }
+
+        let now = Instant::now();
+
+        std::mem::take(&mut self.scheduled)
+            .into_sorted_vec()
+            .into_iter()
+            .rev()
+            .filter(|scheduled| scheduled.outgoing.state.take())
+            .map(|scheduled| DelayedMessage {
+                delay: scheduled.at.saturating_duration_since(now),
+                message: scheduled.outgoing.payload.into_message(),
+            })
+            .collect()
+    }
+
+    async fn absorb(&mut self, frame: Result<ReceivedFrame, WebSocketError>) -> Option<()> {
+        match self.process(frame.ok()?).await {
+            Ok(ControlFlow::Continue(())) => Some(()),
+            _ => None,
+        }
+    }
+
+    async fn fail(
+        &mut self,
+        status: StatusCode,
+        error: WebSocketError,
+    ) -> Result<ControlFlow<()>, WebSocketError> {
+        if self.can_send() {
+            let content = CloseContent {
+                status,
+                reason: None,
+            };
+            self.close_send(content).await?;
+        }
+
+        Err(error)
+    }
+
+    fn idle_deadline(&self) -> Option<Instant> {
+        if self.paused || !self.can_send() {
+            return None;
+        }
+
+        let last_received = self.activity.last_received();
+        let since = self
+            .resumed_at
+            .map_or(last_received, |resumed_at| resumed_at.max(last_received));
+
+        self.limits.idle_timeout.map(|timeout| since + timeout)
+    }
+
+    fn violation(&self, size: usize, fragments: usize) -> Option<StatusCode> {
+        let too_many = self.max_fragments.is_some_and(|max| fragments > max);
+
+        (size > self.limits.max_message_size || too_many).then_some(StatusCode::MessageTooBig)
+    }
+
+    fn discard_message(&mut self, raw_frame: &RawFrame) {
+        self.discarding = match raw_frame.opcode {
+            Opcode::Text | Opcode::Binary | Opcode::Continuation => !raw_frame.fin,
+            _ => self.fragments.is_some() || self.discarding,
+        };
+        self.fragments = None;
+    }
+
+    fn rate_exceeded(&mut self, raw_frame: &RawFrame) -> Option<Instant> {
+        let widen = |(limit, per): (u32, Duration)| (limit as u64, per);
+        let Windows {
+            messages,
+            frames,
+            bytes,
+            control,
+        } = &mut self.windows;
+
+        let new_message = matches!(raw_frame.opcode, Opcode::Text | Opcode::Binary);
+        let is_control = matches!(
+            raw_frame.opcode,
+            Opcode::Close | Opcode::Ping | Opcode::Pong
+        );
+
+        [
+            self.limits
+                .rate_limit
+                .filter(|_| new_message)
+                .and_then(|limit| messages.record(1, widen(limit))),
+            self.limits
+                .frame_rate_limit
+                .and_then(|limit| frames.record(1, widen(limit))),
+            self.limits
+                .byte_rate_limit
+                .and_then(|limit| bytes.record(raw_frame.payload.len() as u64, limit)),
+            self.limits
+                .control_rate_limit
+                .filter(|_| is_control)
+                .and_then(|limit| control.record(1, widen(limit))),
+        ]
+        .into_iter()
+        .flatten()
+        .max()
+    }
+
+    async fn process(&mut self, frame: ReceivedFrame) -> Result<ControlFlow<()>, WebSocketError> {
+        let ReceivedFrame {
+            raw_frame,
+            wire_size,
+        } = frame;
+        self.activity.record_received();
+
+        if self.can_send()
+            && let Some(until) = self.rate_exceeded(&raw_frame)
+        {
+            match self.limits.rate_policy {
+                RatePolicy::Close => {
+                    self.discard_message(&raw_frame);
+
+                    let content = CloseContent {
+                        status: StatusCode::PolicyViolation,
+                        reason: None,
+                    };
+                    self.close_send(content).await?;
+
+                    return Ok(ControlFlow::Continue(()));
+                }
+                RatePolicy::Throttle => self.throttled_until = Some(until),
+            }
+        }
+
+        let buffered = match (raw_frame.opcode, &self.fragments) {
+            (Opcode::Continuation, None) if self.discarding => {
+                self.discarding = !raw_frame.fin;
+
+                return Ok(ControlFlow::Continue(()));
+            }
+            (Opcode::Text | Opcode::Binary, None) if self.discarding => {
+                return Err(InvalidFrame::Inconsistent.into());
+            }
+            (Opcode::Text | Opcode::Binary, None) => Some((0, 0)),
+            (Opcode::Continuation, Some(fragments)) => {
+                Some((fragments.payload.len(), fragments.count))
+            }
+            (Opcode::Text | Opcode::Binary, Some(_)) | (Opcode::Continuation, None) => {
+                return Err(InvalidFrame::Inconsistent.into());
+            }
+            _ => None,
+        };
+
+        if let Some((buffered, count)) = buffered
+            && let Some(status) = self.violation(buffered + raw_frame.payload.len(), count + 1)
+        {
+            self.discard_message(&raw_frame);
+
+            if self.can_send() {
+                let content = CloseContent {
+                    status,
+                    reason: None,
+                };
+                self.close_send(content).await?;
+            }
+
+            return Ok(ControlFlow::Continue(()));
+        }
+
+        let envelope = match raw_frame.opcode {
+            Opcode::Text if raw_frame.fin => {
+                std::str::from_utf8(&raw_frame.payload).map_err(InvalidFrame::from)?;
+
+                Envelope {
+                    message: Message::Text(Text(raw_frame.payload)),
+                    fragments: 1,
+                    wire_size,
+                }
+            }
+            Opcode::Binary if raw_frame.fin => Envelope {
+                message: Message::Binary(Binary(raw_frame.payload)),
+                fragments: 1,
+                wire_size,
+            },
+            Opcode::Text | Opcode::Binary => {
+                let kind = match raw_frame.opcode {
+                    Opcode::Text => MessageKind::Text,
+                    _ => MessageKind::Binary,
+                };
+                let mut fragments = Fragments::new(kind, 1, wire_size);
+                fragments
+                    .append(&raw_frame.payload, false)
+                    .map_err(InvalidFrame::from)?;
+                self.fragments = Some(fragments);
+
+                return Ok(ControlFlow::Continue(()));
+            }
+            Opcode::Continuation => {
+                let Some(fragments) = &mut self.fragments else {
+                    return Err(InvalidFrame::Inconsistent.into());
+                };
+                fragments
+                    .append(&raw_frame.payload, raw_frame.fin)
+                    .map_err(InvalidFrame::from)?;
+                fragments.count += 1;
+                fragments.wire_size += wire_size;
+
+                if !raw_frame.fin {
+                    return Ok(ControlFlow::Continue(()));
+                }
+
+                let Fragments {
+                    kind,
+                    payload,
+                    count,
+                    wire_size,
+                    ..
+                } = self.fragments.take().unwrap();
+                let payload = payload.freeze();
+                let message = match kind {
+                    MessageKind::Text => Message::Text(Text(payload)),
+                    MessageKind::Binary => Message::Binary(Binary(payload)),
+                };
+
+                Envelope {
+                    message,
+                    fragments: count,
+                    wire_size,
+                }
+            }
+            Opcode::Close => {
+                let status = if raw_frame.payload.is_empty() {
+                    None
+                } else {
+                    match CloseContent::try_from(raw_frame.payload) {
+                        Ok(CloseContent { status, reason }) => {
+                            let _ = self.close_reason.set(CloseReason {
+                                code: status,
+                                reason,
+                            });
+
+                            Some(status)
+                        }
+                        Err(e) if self.compliance == Compliance::Lenient => {
+                            log::warn!("accepting malformed close frame: {e}");
+
+                            None
+                        }
+                        Err(e) => return Err(e.into()),
+                    }
+                };
+
+                let result = self.reply_close(status).await;
+                let code = status.unwrap_or(StatusCode::NoStatusReceived);
+                let _ = self.close_reason.set(CloseReason { code, reason: None });
+                self.set_state(State::Closed { code: Some(code) });
+                self.emit(EventKind::CloseFinished { code: Some(code) });
+
+                return result.map(|_| ControlFlow::Break(()));
+            }
+            Opcode::Ping if self.ping_mode == PingMode::Automatic && self.can_send() => {
+                let payload = raw_frame.payload.clone();
+                self.observe_control(raw_frame);
+                self.pong(Binary(payload)).await?;
+
+                return Ok(ControlFlow::Continue(()));
+            }
+            Opcode::Pong => {
+                self.resolve_ping(&raw_frame.payload);
+                self.observe_control(raw_frame);
+
+                return Ok(ControlFlow::Continue(()));
+            }
+            Opcode::Ping => {
+                self.observe_control(raw_frame);
+
+                return Ok(ControlFlow::Continue(()));
+            }
+        };
+
+        let _ = self.receive_tx.send_async(Ok(envelope)).await;
+
+        Ok(ControlFlow::Continue(()))
+    }
+
+    fn start_handler(
+        stream: TcpStream,
+        buffered: Bytes,
+        mask: Mask,
+        config: WebSocketConfig,
+        session: Option<Session>,
+    ) -> Controller {
+        let Session {
+            fragments,
+            pending,
+            scheduled,
+            limits,
+            ping_mode,
+            fragment_size,
+        } = session.unwrap_or_else(|| Session::new(&config));
+        let (send_tx, send_rx) = flume::unbounded();
+        let (receive_tx, receive_rx) = flume::unbounded();
+        for envelope in pending {
+            let _ = receive_tx.send(Ok(envelope));
+        }
+        let (command_tx, command_rx) = flume::unbounded();
+        let (stop_tx, mut stop_rx) = tokio::sync::oneshot::channel();
+        let (state_tx, state_rx) = tokio::sync::watch::channel(State::Open);
+        let (size_check_tx, size_check_rx) = tokio::sync::watch::channel(None);
+        let activity = Arc::new(Activity::new());
+        let handler_activity = activity.clone();
+        let close_reason = Arc::new(OnceLock::new());
+        let handler_close_reason = close_reason.clone();
+        let queue_pressure = Arc::new(QueuePressure::default());
+        let handler_queue_pressure = queue_pressure.clone();
+        let compliance = config.compliance;
+        let lenient_masking = config.lenient_masking || compliance == Compliance::Lenient;
+        let max_frame_size = config.max_frame_size();
+        let max_fragments = config.max_fragments();
+        let raw_text_fragments = config.raw_text_fragments();
+        let work_budget = config.work_budget();
+
+        let handle = tokio::spawn(async move {
+            let (read_half, write_half) = stream.into_split();
+            let (detach_tx, detach_rx) = tokio::sync::oneshot::channel();
+            let (frame_rx, reader_handle) = Reader::start_reader(
+                Prefixed::new(buffered, read_half),
+                mask,
+                lenient_masking,
+                compliance,
+                max_frame_size,
+                size_check_rx,
+                detach_rx,
+            );
+            let mut reader_guard = AbortOnDrop(reader_handle);
+            let mut manager = Handler {
+                stream: BufWriter::new(write_half),
+                mask,
+                receive_tx,
+                control_tx: None,
+                events_tx: None,
+                state_tx,
+                scheduled: BinaryHeap::new(),
+                scheduled_sequence: 0,
+                limits,
+                ping_mode,
+                fragment_size,
+                raw_text_fragments,
+                fragments,
+                discarding: false,
+                max_fragments,
+                compliance,
+                close_deadline: None,
+                pings: Vec::new(),
+                activity: handler_activity,
+                close_reason: handler_close_reason,
+                queue_pressure: handler_queue_pressure,
+                windows: Windows::new(),
+                throttled_until: None,
+                paused: false,
+                resumed_at: None,
+            };
+
+            for DelayedMessage { delay, message } in scheduled {
+                let outgoing = Outgoing {
+                    payload: Payload::Message(message),
+                    state: Arc::new(SendState::default()),
+                    deadline: None,
+                };
+                manager.schedule(Instant::now() + delay, outgoing);
+            }
+
+            let mut stop_polled = false;
+            let mut detach = None;
+            let mut work_left = work_budget;
+
+            loop {
+                let deadline = manager.next_deadline();
+                let idle_deadline = manager.idle_deadline();
+                let ping_deadline = manager.ping_deadline();
+                let close_deadline = manager.close_deadline;
+                let throttled_until = manager.throttled_until;
+
+                let flow = tokio::select! {
+                    biased;
+
+                    raw_frame = frame_rx.recv_async(), if throttled_until.is_none() && !manager.paused => {
+                        let flow = match raw_frame {
+                            Ok(Ok(raw_frame)) => manager.process(raw_frame).await,
+                            Ok(Err(e)) => Err(e),
+                            Err(_) => Ok(ControlFlow::Break(())),
+                        };
+
+                        match flow {
+                            Err(WebSocketError::InvalidFrame(e)) => {
+                                manager.fail(StatusCode::from(&e), e.into()).await
+                            }
+                            flow => flow,
+                        }
+                    },
+                    _ = tokio::time::sleep_until(throttled_until.unwrap_or_else(Instant::now).into()), if throttled_until.is_some() => {
+                        manager.throttled_until = None;
+
+                        Ok(ControlFlow::Continue(()))
+                    },
+                    Ok(command) = command_rx.recv_async() => match command {
+                        Command::Detach(reply_tx) => {
+                            detach = Some(reply_tx);
+
+                            Ok(ControlFlow::Break(()))
+                        }
+                        Command::CloseSend(content) => {
+                            manager.flush_and_close(&send_rx, content).await.map(ControlFlow::Continue)
+                        }
+                        command => manager.command(command).await.map(ControlFlow::Continue),
+                    },
+                    _ = tokio::time::sleep_until(close_deadline.unwrap_or_else(Instant::now).into()), if close_deadline.is_some() => {
+                        let _ = manager.stream.shutdown().await;
+
+                        Ok(ControlFlow::Break(()))
+                    },
+                    _ = tokio::time::sleep_until(idle_deadline.unwrap_or_else(Instant::now).into()), if idle_deadline.is_some() => {
+                        manager.emit(EventKind::IdleTimeout);
+
+                        let content = CloseContent {
+                            status: StatusCode::GoingAway,
+                            reason: None,
+                        };
+
+                        manager.close_send(content).await.map(ControlFlow::Continue)
+                    },
+                    _ = tokio::time::sleep_until(ping_deadline.unwrap_or_else(Instant::now).into()), if ping_deadline.is_some() => {
+                        manager.expire_pings();
+
+                        Ok(ControlFlow::Continue(()))
+                    },
+                    _ = tokio::time::sleep_until(deadline.unwrap_or_else(Instant::now).into()), if deadline.is_some() => {
+                        manager.send_scheduled().await.map(ControlFlow::Continue)
+                    },
+                    Ok(outgoing) = send_rx.recv_async(), if manager.can_send() => {
+                        manager.queue_pressure.record(send_rx.len());
+
+                        manager.send(outgoing).await.map(ControlFlow::Continue)
+                    },
+                    stop = &mut stop_rx, if !stop_polled => {
+                        stop_polled = true;
+
+                        match stop {
+                            Ok(()) if manager.can_send() => {
+                                let content = CloseContent {
+                                    status: StatusCode::NormalClosure,
+                                    reason: None,
+                                };
+
+                                manager.close_send(content).await.map(ControlFlow::Continue)
+                            }
+                            _ => Ok(ControlFlow::Continue(())),
+                        }
+                    },
+                    else => Ok(ControlFlow::Break(())),
+                };
+
+                match flow {
+                    Ok(ControlFlow::Continue(())) => (),
+                    Ok(ControlFlow::Break(())) => break,
+                    Err(e) => {
+                        manager.emit(EventKind::Error(e.kind()));
+                        let _ = manager.receive_tx.send(Err(e));
+
+                        break;
+                    }
+                }
+
+                work_left -= 1;
+                if work_left == 0 {
+                    work_left = work_budget;
+                    tokio::task::yield_now().await;
+                }
+            }
+
+            if let Some(reply_tx) = detach
+                && let Some(prefixed) = manager
+                    .drain(detach_tx, &mut reader_guard.0, &frame_rx, &send_rx)
+                    .await
+            {
+                let scheduled = manager.hand_over();
+                let Handler {
+                    stream,
+                    fragments,
+                    limits,
+                    ping_mode,
+                    fragment_size,
+                    ..
+                } = manager;
+
+                if let Ok(stream) = prefixed.stream.reunite(stream.into_inner()) {
+                    let detached = Detached {
+                        stream,
+                        buffered: prefixed.buffered,
+                        fragments,
+                        scheduled,
+                        limits,
+                        ping_mode,
+                        fragment_size,
+                    };
+                    let _ = reply_tx.send(detached);
+                }
+
+                return;
+            }
+
+            let closed = manager.state_tx.send_if_modified(|state| {
+                if let State::Closed { .. } = state {
+                    return false;
+                }
+
+                *state = State::Closed {
+                    code: Some(StatusCode::AbnormalClosure),
+                };
+
+                true
+            });
+            if closed {
+                let _ = manager.close_reason.set(CloseReason {
+                    code: StatusCode::AbnormalClosure,
+                    reason: None,
+                });
+                manager.emit(EventKind::CloseFinished {
+                    code: Some(StatusCode::AbnormalClosure),
+                });
+            }
+        });
+
+        Controller {
+            send_tx,
+            receive_rx,
+            command_tx,
+            state_rx,
+            stop_tx: Some(stop_tx),
+            handle: Some(handle),
+            drop_policy: DropPolicy::default(),
+            limits,
+            activity,
+            close_reason,
+            queue_pressure,
+            size_check_tx,
+            role: mask.into(),
+            config,
+        }
+    }
+}
+
+pub struct Connection {
+    controller: Controller,
+    info: HandshakeInfo,
+}
+
+impl Connection {
+    pub(crate) fn client_side(
+        stream: TcpStream,
+        buffered: Bytes,
+        info: HandshakeInfo,
+        config: WebSocketConfig,
+    ) -> Self {
+        let controller = Handler::start_handler(stream, buffered, Mask::ClientSide, config, None);
+
+        Self { controller, info }
+    }
+
+    pub(crate) fn server_side(
+        stream: TcpStream,
+        buffered: Bytes,
+        info: HandshakeInfo,
+        config: WebSocketConfig,
+    ) -> Self {
+        let controller = Handler::start_handler(stream, buffered, Mask::ServerSide, config, None);
+
+        Self { controller, info }
+    }
+
+    pub fn role(&self) -> Role {
+        self.controller.role
+    }
+
+    pub fn config(&self) -> &WebSocketConfig {
+        &self.controller.config
+    }
+
+    pub fn handshake_info(&self) -> &HandshakeInfo {
+        &self.info
+    }
+
+    pub fn extensions(&self) -> &Extensions {
+        &self.info.extensions
+    }
+
+    pub fn extensions_mut(&mut self) -> &mut Extensions {
+        &mut self.info.extensions
+    }
+
+    pub async fn send(&self, message: Message) -> Result<SendHandle, WebSocketError> {
+        self.controller.send(Payload::Message(message), None).await
+    }
+
+    pub async fn send_buf(&self, mut buf: impl Buf) -> Result<SendHandle, WebSocketError> {
+        let binary = Binary::from(buf.copy_to_bytes(buf.remaining()));
+
+        self.send(Message::Binary(binary)).await
+    }
+
+    pub async fn send_with_deadline(
+        &self,
+        message: Message,
+        deadline: Instant,
+    ) -> Result<SendHandle, WebSocketError> {
+        self.controller
+            .send(Payload::Message(message), Some(deadline))
+            .await
+    }
+
+    pub async fn send_chunks(
+        &self,
+        kind: MessageKind,
+        chunks: impl IntoIterator<Item = Bytes>,
+    ) -> Result<SendHandle, WebSocketError> {
+        let chunks = chunks.into_iter().collect();
+
+        self.controller
+            .send(Payload::Chunks(kind, chunks), None)
+            .await
+    }
+
+    pub async fn send_after(
+        &self,
+        message: Message,
+        delay: Duration,
+    ) -> Result<SendHandle, WebSocketError> {
+        self.controller
+            .send_at(message, Instant::now() + delay)
+            .await
+    }
+
+    pub async fn send_at(
+        &self,
+        message: Message,
+        at: Instant,
+    ) -> Result<SendHandle, WebSocketError> {
+        self.controller.send_at(message, at).await
+    }
+
+    pub async fn receive(&self) -> Result<Message, WebSocketError> {
+        self.controller.receive().await.map(Envelope::into_message)
+    }
+
+    pub async fn receive_envelope(&self) -> Result<Envelope, WebSocketError> {
+        self.controller.receive().await
+    }
+
+    pub async fn close_send(
+        &self,
+        status: StatusCode,
+        reason: Option<Text>,
+    ) -> Result<(), WebSocketError> {
+        self.controller.close_send(status, reason).await
+    }
+
+    pub async fn close(
+        &self,
+        status: StatusCode,
+        reason: Option<Text>,
+    ) -> Result<Option<StatusCode>, WebSocketError> {
+        self.controller.close(status, reason).await
+    }
+
+    pub async fn ping(
+        &self,
+        payload: Binary,
+        timeout: Duration,
+    ) -> Result<Duration, WebSocketError> {
+        self.controller.ping(payload, timeout).await
+    }
+
+    pub async fn pong(&self, payload: Binary) -> Result<(), WebSocketError> {
+        self.controller.pong(payload).await
+    }
+
+    pub async fn observe_control_frames(
+        &self,
+    ) -> Result<flume::Receiver<ControlFrame>, WebSocketError> {
+        self.controller.observe_control_frames().await
+    }
+
+    pub async fn observe_events(&self) -> Result<flume::Receiver<Event>, WebSocketError> {
+        self.controller.observe_events().await
+    }
+
+    pub fn state(&self) -> State {
+        self.controller.state()
+    }
+
+    pub fn close_reason(&self) -> Option<&CloseReason> {
+        self.controller.close_reason.get()
+    }
+
+    pub fn last_received(&self) -> Instant {
+        self.controller.activity.last_received()
+    }
+
+    pub fn last_sent(&self) -> Instant {
+        self.controller.activity.last_sent()
+    }
+
+    pub fn set_size_check(&self, check: impl Fn(usize) -> bool + Send + Sync + 'static) {
+        self.controller
+            .size_check_tx
+            .send_replace(Some(Arc::new(check)));
+    }
+
+    pub fn clear_size_check(&self) {
+        self.controller.size_check_tx.send_replace(None);
+    }
+
+    pub fn limits(&self) -> Limits {
+        self.controller.limits
+    }
+
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.controller.set_limits(limits);
+    }
+
+    pub fn queue_stats(&self) -> QueueStats {
+        self.controller.queue_pressure.stats()
+    }
+
+    pub fn set_queue_watermark(&self, watermark: usize) {
+        self.controller.queue_pressure.set_watermark(watermark);
+    }
+
+    pub fn set_fragment_size(&self, fragment_size: usize) {
+        self.controller.set_fragment_size(fragment_size);
+    }
+
+    pub fn pause_receiving(&self) {
+        self.controller.set_receiving(false);
+    }
+
+    pub fn resume_receiving(&self) {
+        self.controller.set_receiving(true);
+    }
+
+    pub fn set_ping_mode(&self, ping_mode: PingMode) {
+        self.controller.set_ping_mode(ping_mode);
+    }
+
+    pub fn set_drop_policy(&mut self, policy: DropPolicy) {
+        self.controller.drop_policy = policy;
+    }
+
+    pub async fn into_inner(self) -> Result<(TcpStream, ConnectionState), WebSocketError> {
+        if self.state() != State::Open {
+            return Err(WebSocketError::ConnectionClosed);
+        }
+
+        let role = self.controller.role;
+        let (detached, pending) = self.controller.detach().await?;
+
+        let partial = detached.fragments.map(|fragments| PartialMessage {
+            kind: fragments.kind,
+            payload: fragments.payload.to_vec(),
+            fragments: fragments.count,
+            wire_size: fragments.wire_size,
+        });
+        let state = ConnectionState {
+            role,
+            path: self.info.path,
+            version: self.info.version,
+            headers: self
+                .info
+                .headers
+                .into_iter()
+                .map(|(name, value)| (name, value.to_vec()))
+                .collect(),
+            buffered: detached.buffered.to_vec(),
+            partial,
+            pending,
+            scheduled: detached.scheduled,
+            limits: detached.limits,
+            ping_mode: detached.ping_mode,
+            fragment_size: detached.fragment_size,
+        };
+
+        Ok((detached.stream, state))
+    }
+
+    pub fn from_state(
+        stream: TcpStream,
+        state: ConnectionState,
+        config: WebSocketConfig,
+    ) -> Result<Self, WebSocketError> {
+        let mask = match state.role {
+            Role::Client => Mask::ClientSide,
+            Role::Server => Mask::ServerSide,
+        };
+        let fragments = match state.partial {
+            Some(partial) => {
+                let mut fragments =
+                    Fragments::new(partial.kind, partial.fragments, partial.wire_size);
+                fragments
+                    .append(&partial.payload, false)
+                    .map_err(InvalidFrame::from)?;
+
+                Some(fragments)
+            }
+            None => None,
+        };
+        let info = HandshakeInfo {
+            path: state.path,
+            version: state.version,
+            headers: state
+                .headers
+                .into_iter()
+                .map(|(name, value)| (name, Bytes::from(value)))
+                .collect(),
+            extensions: Extensions::new(),
+        };
+
+        let session = Session {
+            fragments,
+            pending: state.pending,
+            scheduled: state.scheduled,
+            limits: state.limits,
+            ping_mode: state.ping_mode,
+            fragment_size: state.fragment_size,
+        };
+        let controller = Handler::start_handler(
+            stream,
+            Bytes::from(state.buffered),
+            mask,
+            config,
+            Some(session),
+        );
+
+        Ok(Self { controller, info })
+    }
+
+    pub async fn stop(self) {
+        self.controller.stop().await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use claim::{assert_err, assert_ok};
+
+    use super::*;
+
+    fn feed_all(chunks: &[&[u8]]) -> Result<(), std::str::Utf8Error> {
+        let mut utf8 = Utf8Validator::default();
+        for chunk in chunks {
+            utf8.feed(chunk)?;
+        }
+
+        utf8.finish()
+    }
+
+    #[test]
+    fn validator_accepts_characters_split_across_chunks() {
+        let euro = "€".as_bytes();
+
+        assert_ok!(feed_all(&[b"a", &euro[..1], &euro[1..2], &euro[2..], b"b"]));
+        assert_ok!(feed_all(&[&euro[..2], &[], &euro[2..]]));
+    }
+
+    #[test]
+    fn validator_rejects_invalid_sequences() {
+        let euro = "€".as_bytes();
+
+        assert_err!(feed_all(&[b"a", &[0xFF]]));
+        assert_err!(feed_all(&[&euro[..2], b"a"]));
+        assert_err!(feed_all(&[b"a", &euro[..2]]));
     }
 }
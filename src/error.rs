@@ -1,15 +1,28 @@
+use std::fmt;
+
+use bytes::Bytes;
 use thiserror::Error;
 
-use crate::connection::{MAX_FRAME_PAYLOAD_SIZE, MAX_MESSAGE_SIZE};
+use crate::{
+    connection::MAX_CONTROL_PAYLOAD_SIZE,
+    handshake::{MAX_HEAD_SIZE, MAX_REQUEST_LINE_SIZE},
+};
 
 #[derive(Debug, Error)]
+#[non_exhaustive]
 pub enum InvalidFrame {
     #[error("unknown opcode `{0}`")]
     Opcode(u8),
-    #[error("unknown status code `{0}`")]
+    #[error("invalid status code `{0}`")]
     Code(u16),
-    #[error("payload surpasses size limit: {MAX_FRAME_PAYLOAD_SIZE}")]
+    #[error("payload surpasses frame size limit")]
     PayloadSize,
+    #[error("payload of {0} bytes was rejected")]
+    PayloadRejected(usize),
+    #[error(
+        "control frame `{opcode}` payload of {len} bytes surpasses size limit: {MAX_CONTROL_PAYLOAD_SIZE}"
+    )]
+    ControlTooLarge { opcode: u8, len: usize },
     #[error("text isn't UTF-8 compliant: {0}")]
     Text(#[from] std::str::Utf8Error),
     #[error("inconsistent data")]
@@ -17,25 +30,116 @@ pub enum InvalidFrame {
 }
 
 #[derive(Debug, Error)]
+#[non_exhaustive]
 pub enum InvalidHandshake {
     #[error("failed to parse request: {0}")]
     HttpRequestParser(#[source] httparse::Error),
     #[error("failed to parse response: {0}")]
     HttpResponseParser(#[source] httparse::Error),
-    #[error("does not meet the specified requirements")]
-    NonConformant,
+    #[error("request line is longer than {MAX_REQUEST_LINE_SIZE} bytes")]
+    RequestLineTooLong,
+    #[error("message head is longer than {MAX_HEAD_SIZE} bytes")]
+    HeadTooLarge,
+    #[error("request method isn't GET")]
+    Method,
+    #[error("HTTP version isn't 1.1")]
+    Version,
+    #[error("request target isn't in origin-form")]
+    Target,
+    #[error("does not meet the specified requirements: {0}")]
+    NonConformant(Requirement),
+    #[error("`{0}` header appears more than once")]
+    DuplicateHeader(&'static str),
+    #[error("authentication was rejected")]
+    Unauthorized,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Requirement {
+    Host,
+    Upgrade,
+    Connection,
+    Key,
+    Version,
+    Status,
+    Accept,
+    Extensions,
+}
+
+impl fmt::Display for Requirement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let requirement = match self {
+            Requirement::Host => "valid Host header",
+            Requirement::Upgrade => "Upgrade: websocket header",
+            Requirement::Connection => "Connection: Upgrade header",
+            Requirement::Key => "base64 Sec-WebSocket-Key header",
+            Requirement::Version => "Sec-WebSocket-Version: 13 header",
+            Requirement::Status => "101 Switching Protocols status",
+            Requirement::Accept => "matching Sec-WebSocket-Accept header",
+            Requirement::Extensions => "no unrequested extensions or subprotocols",
+        };
+
+        f.write_str(requirement)
+    }
 }
 
 #[derive(Debug, Error)]
+#[non_exhaustive]
 pub enum WebSocketError {
     #[error("handshake is invalid: {0}")]
     InvalidHandshake(#[from] InvalidHandshake),
+    #[error("handshake was rejected: {error}")]
+    RejectedHandshake {
+        #[source]
+        error: InvalidHandshake,
+        request: Bytes,
+    },
+    #[error("server rejected the handshake with status {status}")]
+    HandshakeRejected {
+        status: u16,
+        headers: Vec<(String, Bytes)>,
+        body: Bytes,
+    },
     #[error("something went wrong with the connection: {0}")]
     Io(#[from] std::io::Error),
     #[error("invalid frame: {0}")]
     InvalidFrame(#[from] InvalidFrame),
-    #[error("message surpasses size limit: {MAX_MESSAGE_SIZE}")]
+    #[error("message surpasses size limit")]
     InvalidMessageSize,
     #[error("connection is closed")]
     ConnectionClosed,
+    #[error("connection was handed over")]
+    HandedOver,
+    #[error("no pong received before timeout")]
+    PingTimeout,
+    #[error("no response received before timeout")]
+    CallTimeout,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    Handshake,
+    Protocol,
+    Io,
+    Limit,
+    Closed,
+    Timeout,
+}
+
+impl WebSocketError {
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Self::InvalidHandshake(_)
+            | Self::RejectedHandshake { .. }
+            | Self::HandshakeRejected { .. } => ErrorKind::Handshake,
+            Self::Io(_) => ErrorKind::Io,
+            Self::InvalidFrame(InvalidFrame::PayloadSize | InvalidFrame::PayloadRejected(_))
+            | Self::InvalidMessageSize => ErrorKind::Limit,
+            Self::InvalidFrame(_) => ErrorKind::Protocol,
+            Self::ConnectionClosed | Self::HandedOver => ErrorKind::Closed,
+            Self::PingTimeout | Self::CallTimeout => ErrorKind::Timeout,
+        }
+    }
 }
@@ -1,43 +1,94 @@
-use std::net::SocketAddr;
+use std::{net::SocketAddr, time::Duration};
 
+use bytes::Bytes;
 use tokio::{
-    io::{AsyncBufReadExt, AsyncWriteExt, BufStream},
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
     net::TcpStream,
+    time,
 };
 
 use crate::{
-    connection::Connection,
-    error::WebSocketError,
-    handshake::{ClientHandshake, ParsedHeadersBuf, ServerHanshake, parse_request, parse_response},
+    auth::{Authenticator, Credentials},
+    connection::{Connection, WebSocketConfig, encode_client_frame},
+    error::{InvalidHandshake, WebSocketError},
+    handshake::{
+        ClientHandshake, MAX_CAPTURED_REQUEST_SIZE, MAX_HEAD_SIZE, MAX_REJECTION_BODY_SIZE,
+        MAX_REQUEST_LINE_SIZE, PROTOCOL_VERSION, ParsedHeadersBuf, ServerHandshake,
+        authentication_challenges, offered_versions, parse_request, parse_response,
+        raw_rejection_response, redact_query_param, rejected_response, response_info,
+    },
+    info::HandshakeInfo,
+    message::Message,
 };
 
-struct Buf {
-    bstream: BufStream<TcpStream>,
+const REJECTION_BODY_TIMEOUT: Duration = Duration::from_secs(1);
+
+const MAX_HANDSHAKE_ATTEMPTS: usize = 4;
+
+pub(crate) struct Buf {
+    bstream: BufReader<TcpStream>,
 }
 
 impl Buf {
-    fn new(stream: TcpStream) -> Self {
-        let bstream = BufStream::new(stream);
+    pub(crate) fn new(stream: TcpStream) -> Self {
+        let bstream = BufReader::new(stream);
 
         Self { bstream }
     }
 
-    async fn read_raw_http(&mut self) -> Result<Vec<u8>, tokio::io::Error> {
+    pub(crate) async fn read_raw_http(&mut self) -> Result<Vec<u8>, WebSocketError> {
         let mut raw = Vec::new();
         loop {
-            if self.bstream.read_until(b'\n', &mut raw).await? == 0 {
-                break;
+            let remaining = (MAX_HEAD_SIZE - raw.len()) as u64;
+            let read = (&mut self.bstream)
+                .take(remaining)
+                .read_until(b'\n', &mut raw)
+                .await?;
+
+            let first_line = raw
+                .iter()
+                .position(|&b| b == b'\n')
+                .map_or(raw.len(), |end| end + 1);
+            if first_line > MAX_REQUEST_LINE_SIZE {
+                return Err(InvalidHandshake::RequestLineTooLong.into());
             }
 
             if raw.ends_with(b"\r\n\r\n") {
                 break;
             }
+
+            if raw.len() >= MAX_HEAD_SIZE {
+                return Err(InvalidHandshake::HeadTooLarge.into());
+            }
+
+            if read == 0 {
+                break;
+            }
         }
 
         Ok(raw)
     }
 
-    async fn write_raw_http(&mut self, raw: &[u8]) -> Result<(), tokio::io::Error> {
+    async fn read_raw_body(
+        &mut self,
+        content_length: Option<usize>,
+    ) -> Result<Bytes, WebSocketError> {
+        let limit = content_length.map_or(MAX_REJECTION_BODY_SIZE, |length| {
+            length.min(MAX_REJECTION_BODY_SIZE)
+        });
+        let mut body = Vec::with_capacity(limit);
+
+        let mut body_stream = (&mut self.bstream).take(limit as u64);
+        if let Ok(read) =
+            time::timeout(REJECTION_BODY_TIMEOUT, body_stream.read_to_end(&mut body)).await
+        {
+            read?;
+        }
+
+        Ok(body.into())
+    }
+
+    pub(crate) async fn write_raw_http(&mut self, raw: &[u8]) -> Result<(), tokio::io::Error> {
         self.bstream.write_all(raw).await?;
         self.bstream.flush().await?;
 
@@ -45,59 +96,241 @@ impl Buf {
     }
 }
 
+impl Buf {
+    pub(crate) fn into_parts(self) -> (TcpStream, Bytes) {
+        let buffered = Bytes::copy_from_slice(self.bstream.buffer());
+
+        (self.bstream.into_inner(), buffered)
+    }
+}
+
 impl From<Buf> for TcpStream {
     fn from(buf: Buf) -> Self {
         buf.bstream.into_inner()
     }
 }
 
+async fn reject(
+    buf: &mut Buf,
+    config: &WebSocketConfig,
+    e: InvalidHandshake,
+    raw_request: Option<&[u8]>,
+) -> WebSocketError {
+    let raw_response = raw_rejection_response(&e);
+
+    if let Err(io) = buf.write_raw_http(&raw_response).await {
+        return io.into();
+    }
+
+    config.log_exchange(raw_request.unwrap_or_default(), &raw_response);
+
+    match raw_request {
+        Some(raw_request) => {
+            let captured = raw_request.len().min(MAX_CAPTURED_REQUEST_SIZE);
+
+            WebSocketError::RejectedHandshake {
+                error: e,
+                request: Bytes::copy_from_slice(&raw_request[..captured]),
+            }
+        }
+        None => e.into(),
+    }
+}
+
 pub async fn accept(stream: TcpStream) -> Result<Connection, WebSocketError> {
+    accept_authenticated(stream, &()).await
+}
+
+pub async fn accept_authenticated<A: Authenticator>(
+    stream: TcpStream,
+    authenticator: &A,
+) -> Result<Connection, WebSocketError> {
+    accept_with_config(stream, authenticator, WebSocketConfig::default()).await
+}
+
+pub async fn accept_with_config<A: Authenticator>(
+    stream: TcpStream,
+    authenticator: &A,
+    config: WebSocketConfig,
+) -> Result<Connection, WebSocketError> {
     let mut buf = Buf::new(stream);
 
-    let raw_request = buf.read_raw_http().await?;
+    let raw_request = match buf.read_raw_http().await {
+        Ok(raw_request) => raw_request,
+        Err(WebSocketError::InvalidHandshake(e)) => {
+            return Err(reject(&mut buf, &config, e, None).await);
+        }
+        Err(e) => return Err(e),
+    };
+    let logged_request = match authenticator.redacted_query_param() {
+        Some(name) => redact_query_param(&raw_request, name),
+        None => Bytes::copy_from_slice(&raw_request),
+    };
 
     let mut headers = ParsedHeadersBuf::new();
-    let request = parse_request(&raw_request, &mut headers)?;
-
-    let handshake = match ServerHanshake::try_from_request(&request) {
+    let handshake = parse_request(&raw_request, &mut headers).and_then(|request| {
+        ServerHandshake::try_from_request(&request, config.compliance()).map(|h| (h, request))
+    });
+    let (handshake, request) = match handshake {
         Ok(handshake) => handshake,
-        Err(e) => {
-            buf.write_raw_http(&b"HTTP/1.1 400 Bad Request\r\n\r\n"[..])
-                .await?;
-
-            return Err(e.into());
-        }
+        Err(e) => return Err(reject(&mut buf, &config, e, Some(&logged_request)).await),
     };
 
-    let raw_response = handshake.into_raw_response();
+    let mut info = HandshakeInfo::from(&request);
+    if let Err(rejection) = authenticator.authenticate(&mut info).await {
+        let raw_response = rejection.into_raw_response();
+
+        buf.write_raw_http(&raw_response).await?;
+        config.log_exchange(&logged_request, &raw_response);
+
+        return Err(InvalidHandshake::Unauthorized.into());
+    }
+
+    let raw_response = handshake
+        .with_metadata(config.metadata_header())
+        .into_raw_response();
 
     buf.write_raw_http(&raw_response).await?;
+    config.log_exchange(&logged_request, &raw_response);
 
-    let stream = buf.into();
-    let connection = Connection::server_side(stream);
+    let (stream, buffered) = buf.into_parts();
+    let connection = Connection::server_side(stream, buffered, info, config);
 
     Ok(connection)
 }
 
 pub async fn connect(addr: SocketAddr) -> Result<Connection, WebSocketError> {
-    let stream = TcpStream::connect(addr).await?;
+    connect_with_path(addr, "/").await
+}
 
-    let mut buf = Buf::new(stream);
+pub async fn connect_with_path(addr: SocketAddr, path: &str) -> Result<Connection, WebSocketError> {
+    connect_with_config(addr, path, WebSocketConfig::default()).await
+}
 
-    let handshake = ClientHandshake::new(addr);
-    let request = handshake.raw_request();
+pub async fn connect_with_config(
+    addr: SocketAddr,
+    path: &str,
+    config: WebSocketConfig,
+) -> Result<Connection, WebSocketError> {
+    connect_authenticated(addr, path, None, config, None).await
+}
 
-    buf.write_raw_http(&request).await?;
+/// Writes `message` right behind the upgrade request, without waiting for
+/// the server's response. This saves a round trip, but the server must
+/// keep the bytes it reads past the request head, as `accept` does. If the
+/// handshake is rejected, the message is never delivered.
+pub async fn connect_and_send(
+    addr: SocketAddr,
+    path: &str,
+    config: WebSocketConfig,
+    message: Message,
+) -> Result<Connection, WebSocketError> {
+    if message.size() > config.max_message_size() {
+        return Err(WebSocketError::InvalidMessageSize);
+    }
 
-    let raw_response = buf.read_raw_http().await?;
+    let first_frame = encode_client_frame(message);
 
-    let mut headers = ParsedHeadersBuf::new();
-    let response = parse_response(&raw_response, &mut headers)?;
+    connect_authenticated(addr, path, None, config, Some(first_frame)).await
+}
 
-    handshake.validate_response(&response)?;
+pub async fn connect_with_credentials(
+    addr: SocketAddr,
+    path: &str,
+    credentials: &Credentials,
+) -> Result<Connection, WebSocketError> {
+    connect_authenticated(
+        addr,
+        path,
+        Some(credentials),
+        WebSocketConfig::default(),
+        None,
+    )
+    .await
+}
 
-    let stream = buf.into();
-    let connection = Connection::client_side(stream);
+async fn connect_authenticated(
+    addr: SocketAddr,
+    path: &str,
+    credentials: Option<&Credentials>,
+    config: WebSocketConfig,
+    first_frame: Option<Bytes>,
+) -> Result<Connection, WebSocketError> {
+    let metadata = config.metadata_header();
+    let mut versions = config.protocol_versions().iter().copied();
+    let mut handshake = ClientHandshake::new(addr, path)
+        .with_metadata(metadata.clone())
+        .with_version(versions.next().unwrap_or(PROTOCOL_VERSION));
+    let mut credentials = credentials;
 
-    Ok(connection)
+    for attempt in 1..=MAX_HANDSHAKE_ATTEMPTS {
+        let retry = attempt < MAX_HANDSHAKE_ATTEMPTS;
+        let stream = TcpStream::connect(addr).await?;
+
+        let mut buf = Buf::new(stream);
+
+        let request = handshake.raw_request();
+
+        match &first_frame {
+            Some(first_frame) => {
+                buf.write_raw_http(&[&request[..], first_frame].concat())
+                    .await?
+            }
+            None => buf.write_raw_http(&request).await?,
+        }
+
+        let raw_response = buf.read_raw_http().await?;
+        config.log_exchange(&request, &raw_response);
+
+        let mut headers = ParsedHeadersBuf::new();
+        let response = parse_response(&raw_response, &mut headers)?;
+
+        if retry
+            && let Some(challenges) = authentication_challenges(&response)
+            && let Some(authorization) = credentials
+                .take()
+                .and_then(|credentials| credentials.authorization(&challenges, "GET", path))
+        {
+            handshake = ClientHandshake::new(addr, path)
+                .with_authorization(authorization)
+                .with_metadata(metadata.clone())
+                .with_version(handshake.version());
+
+            continue;
+        }
+
+        if retry
+            && let Some(offered) = offered_versions(&response)
+            && let Some(version) = versions
+                .find(|version| *version != handshake.version() && offered.contains(version))
+        {
+            handshake = handshake.with_version(version);
+
+            continue;
+        }
+
+        if let Some((status, headers)) = rejected_response(&response) {
+            let content_length = headers
+                .iter()
+                .find(|(name, _)| name.eq_ignore_ascii_case("Content-Length"))
+                .and_then(|(_, value)| std::str::from_utf8(value).ok()?.trim().parse().ok());
+            let body = buf.read_raw_body(content_length).await?;
+
+            return Err(WebSocketError::HandshakeRejected {
+                status,
+                headers,
+                body,
+            });
+        }
+
+        handshake.validate_response(&response)?;
+        let info = response_info(path, handshake.version(), &response);
+
+        let (stream, buffered) = buf.into_parts();
+        let connection = Connection::client_side(stream, buffered, info, config);
+
+        return Ok(connection);
+    }
+
+    unreachable!("the last handshake attempt never retries")
 }
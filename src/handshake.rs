@@ -1,20 +1,44 @@
+pub mod client;
+pub mod server;
+
 use base64::{Engine, prelude::BASE64_STANDARD};
 use bytes::{BufMut, Bytes, BytesMut};
 use rand::Rng;
 use sha1::{Digest, Sha1};
 use std::{
-    net::SocketAddr,
+    net::{Ipv6Addr, SocketAddr},
     ops::{Deref, DerefMut},
 };
 
-use crate::error::InvalidHandshake;
+use crate::{
+    connection::Compliance,
+    error::{InvalidHandshake, Requirement},
+    extensions::Extensions,
+    info::{HandshakeInfo, percent_decode},
+};
 
 pub const MAX_HEADERS: usize = 124;
 
+pub const MAX_REQUEST_LINE_SIZE: usize = 8 * 1024;
+
+pub const MAX_HEAD_SIZE: usize = 16 * 1024;
+
+pub const MAX_CAPTURED_REQUEST_SIZE: usize = 1024;
+
+pub const MAX_REJECTION_BODY_SIZE: usize = 4 * 1024;
+
+pub const MAX_LOGGED_EXCHANGE_SIZE: usize = 4 * 1024;
+
+pub const METADATA_HEADER: &str = "X-WS-Meta";
+
+pub const PROTOCOL_VERSION: u16 = 13;
+
 const MAGIC_STRING: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
 
+const UNIQUE_HEADERS: [&str; 3] = ["Host", "Sec-WebSocket-Key", "Sec-WebSocket-Version"];
+
 #[derive(Debug, Default)]
-pub struct ParsedHeadersBuf<'h>(Vec<httparse::Header<'h>>);
+pub(crate) struct ParsedHeadersBuf<'h>(Vec<httparse::Header<'h>>);
 
 impl ParsedHeadersBuf<'_> {
     pub fn new() -> Self {
@@ -45,9 +69,9 @@ impl DerefMut for ParsedHeadersBuf<'_> {
 }
 
 #[derive(Debug)]
-pub struct ParsedResponse<'r>(httparse::Response<'r, 'r>);
+pub(crate) struct ParsedResponse<'r>(httparse::Response<'r, 'r>);
 
-pub fn parse_response<'r>(
+pub(crate) fn parse_response<'r>(
     raw: &'r [u8],
     headers_buf: &'r mut ParsedHeadersBuf<'r>,
 ) -> Result<ParsedResponse<'r>, InvalidHandshake> {
@@ -68,9 +92,9 @@ impl<'r> Deref for ParsedResponse<'r> {
 }
 
 #[derive(Debug)]
-pub struct ParsedRequest<'r>(httparse::Request<'r, 'r>);
+pub(crate) struct ParsedRequest<'r>(httparse::Request<'r, 'r>);
 
-pub fn parse_request<'r>(
+pub(crate) fn parse_request<'r>(
     raw: &'r [u8],
     headers_buf: &'r mut ParsedHeadersBuf<'r>,
 ) -> Result<ParsedRequest<'r>, InvalidHandshake> {
@@ -82,6 +106,155 @@ pub fn parse_request<'r>(
     Ok(ParsedRequest(request))
 }
 
+impl From<&ParsedRequest<'_>> for HandshakeInfo {
+    fn from(request: &ParsedRequest<'_>) -> Self {
+        let path = request.0.path.unwrap_or("/").to_string();
+        let headers = request
+            .0
+            .headers
+            .iter()
+            .map(|h| (h.name.to_string(), Bytes::copy_from_slice(h.value)))
+            .collect();
+
+        HandshakeInfo {
+            path,
+            version: PROTOCOL_VERSION,
+            headers,
+            extensions: Extensions::new(),
+        }
+    }
+}
+
+pub(crate) fn response_info(
+    path: &str,
+    version: u16,
+    response: &ParsedResponse<'_>,
+) -> HandshakeInfo {
+    let headers = response
+        .0
+        .headers
+        .iter()
+        .map(|h| (h.name.to_string(), Bytes::copy_from_slice(h.value)))
+        .collect();
+
+    HandshakeInfo {
+        path: path.to_string(),
+        version,
+        headers,
+        extensions: Extensions::new(),
+    }
+}
+
+fn is_origin_form(target: &str) -> bool {
+    let bytes = target.as_bytes();
+
+    target.starts_with('/')
+        && bytes.iter().all(|&b| b.is_ascii_graphic() && b != b'#')
+        && bytes.iter().enumerate().all(|(i, &b)| {
+            b != b'%'
+                || bytes
+                    .get(i + 1..i + 3)
+                    .is_some_and(|hex| hex.iter().all(u8::is_ascii_hexdigit))
+        })
+}
+
+pub(crate) fn raw_rejection_response(error: &InvalidHandshake) -> Bytes {
+    let status = match error {
+        InvalidHandshake::RequestLineTooLong => &b"414 URI Too Long"[..],
+        InvalidHandshake::HeadTooLarge => &b"431 Request Header Fields Too Large"[..],
+        InvalidHandshake::Method => &b"405 Method Not Allowed\r\nAllow: GET"[..],
+        InvalidHandshake::Version => &b"505 HTTP Version Not Supported"[..],
+        InvalidHandshake::NonConformant(Requirement::Version) => {
+            &b"426 Upgrade Required\r\nSec-WebSocket-Version: 13"[..]
+        }
+        _ => &b"400 Bad Request"[..],
+    };
+
+    let mut buf = BytesMut::new();
+
+    buf.put(&b"HTTP/1.1 "[..]);
+    buf.put(status);
+    buf.put(&b"\r\n\r\n"[..]);
+
+    buf.into()
+}
+
+pub(crate) fn is_sensitive_header(name: &str) -> bool {
+    [
+        "Authorization",
+        "Proxy-Authorization",
+        "Cookie",
+        "Set-Cookie",
+    ]
+    .iter()
+    .any(|sensitive| name.eq_ignore_ascii_case(sensitive))
+}
+
+pub(crate) fn redact_raw_http(raw: &[u8], redact: impl Fn(&str) -> bool) -> Bytes {
+    let raw = &raw[..raw.len().min(MAX_LOGGED_EXCHANGE_SIZE)];
+    let mut buf = BytesMut::with_capacity(raw.len());
+
+    for (i, line) in raw.split_inclusive(|&b| b == b'\n').enumerate() {
+        let header = (i > 0)
+            .then(|| line.iter().position(|&b| b == b':'))
+            .flatten()
+            .and_then(|colon| std::str::from_utf8(&line[..colon]).ok());
+
+        match header {
+            Some(name) if redact(name.trim()) => {
+                buf.put(name.as_bytes());
+                buf.put(&b": [redacted]"[..]);
+                if line.ends_with(b"\r\n") {
+                    buf.put(&b"\r\n"[..]);
+                } else if line.ends_with(b"\n") {
+                    buf.put(&b"\n"[..]);
+                }
+            }
+            _ => buf.put(line),
+        }
+    }
+
+    buf.into()
+}
+
+pub(crate) fn redact_query_param(raw: &[u8], name: &str) -> Bytes {
+    let line_end = raw
+        .iter()
+        .position(|&b| b == b'\n')
+        .map_or(raw.len(), |end| end + 1);
+    let Ok(line) = std::str::from_utf8(&raw[..line_end]) else {
+        return Bytes::copy_from_slice(raw);
+    };
+    let mut parts = line.splitn(3, ' ');
+    let (Some(method), Some(target), Some(rest)) = (parts.next(), parts.next(), parts.next())
+    else {
+        return Bytes::copy_from_slice(raw);
+    };
+    let Some((path, query)) = target.split_once('?') else {
+        return Bytes::copy_from_slice(raw);
+    };
+
+    let query = query
+        .split('&')
+        .map(|param| {
+            let key = param.split_once('=').map_or(param, |(key, _)| key);
+
+            if percent_decode(key, true) == name {
+                format!("{key}=[redacted]")
+            } else {
+                param.to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("&");
+
+    let mut buf = BytesMut::with_capacity(raw.len());
+    buf.put(format!("{method} {path}?{query} {rest}").as_bytes());
+    buf.put(&raw[line_end..]);
+
+    buf.into()
+}
+
 #[derive(Debug)]
 struct HeaderObserver<'h>(&'h httparse::Header<'h>);
 
@@ -112,6 +285,51 @@ impl<'h> From<&'h httparse::Header<'h>> for HeaderObserver<'h> {
     }
 }
 
+fn host_header(addr: &SocketAddr) -> String {
+    match addr {
+        SocketAddr::V4(addr) => addr.to_string(),
+        SocketAddr::V6(addr) if addr.scope_id() != 0 => {
+            format!("[{}%25{}]:{}", addr.ip(), addr.scope_id(), addr.port())
+        }
+        SocketAddr::V6(addr) => format!("[{}]:{}", addr.ip(), addr.port()),
+    }
+}
+
+fn is_valid_port(port: Option<&str>) -> bool {
+    port.is_none_or(|port| port.parse::<u16>().is_ok())
+}
+
+fn is_valid_host(raw: &[u8]) -> bool {
+    let Ok(host) = std::str::from_utf8(raw) else {
+        return false;
+    };
+
+    if let Some(bracketed) = host.strip_prefix('[') {
+        let Some((ip, rest)) = bracketed.split_once(']') else {
+            return false;
+        };
+        let ip = ip.split_once("%25").map_or(ip, |(ip, _)| ip);
+        let port = match rest.strip_prefix(':') {
+            Some(port) => Some(port),
+            None if rest.is_empty() => None,
+            None => return false,
+        };
+
+        return ip.parse::<Ipv6Addr>().is_ok() && is_valid_port(port);
+    }
+
+    let (name, port) = match host.split_once(':') {
+        Some((name, port)) => (name, Some(port)),
+        None => (host, None),
+    };
+    let valid_name = !name.is_empty()
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'.' | b'-' | b'_' | b'~'));
+
+    valid_name && is_valid_port(port)
+}
+
 #[derive(Debug)]
 struct Key(Bytes);
 
@@ -148,32 +366,75 @@ impl Deref for Key {
 }
 
 #[derive(Debug)]
-pub struct ClientHandshake {
+pub(crate) struct ClientHandshake {
     addr: SocketAddr,
+    path: String,
     key: Key,
+    authorization: Option<String>,
+    metadata: Option<Bytes>,
+    version: u16,
 }
 
 impl ClientHandshake {
-    pub fn new(addr: SocketAddr) -> Self {
+    pub fn new(addr: SocketAddr, path: &str) -> Self {
+        let path = path.to_string();
         let key = Key::generate();
 
-        Self { addr, key }
+        Self {
+            addr,
+            path,
+            key,
+            authorization: None,
+            metadata: None,
+            version: PROTOCOL_VERSION,
+        }
+    }
+
+    pub fn with_authorization(mut self, authorization: String) -> Self {
+        self.authorization = Some(authorization);
+
+        self
+    }
+
+    pub fn with_metadata(mut self, metadata: Option<Bytes>) -> Self {
+        self.metadata = metadata;
+
+        self
+    }
+
+    pub fn with_version(mut self, version: u16) -> Self {
+        self.version = version;
+
+        self
+    }
+
+    pub fn version(&self) -> u16 {
+        self.version
     }
 
     pub fn raw_request(&self) -> Bytes {
         let mut buf = BytesMut::new();
 
+        buf.put(&b"GET "[..]);
+        buf.put(self.path.as_bytes());
         buf.put(
-            &b"\
-                GET /chat HTTP/1.1\r\n\
+            &b" HTTP/1.1\r\n\
                 Upgrade: websocket\r\n\
                 Connection: Upgrade\r\n\
-                Sec-WebSocket-Version: 13\r\n\
-                Sec-WebSocket-Key: "[..],
+                Sec-WebSocket-Version: "[..],
         );
+        buf.put(self.version.to_string().as_bytes());
+        buf.put(&b"\r\nSec-WebSocket-Key: "[..]);
         buf.put(&self.key[..]);
         buf.put(&b"\r\nHost: "[..]);
-        buf.put(format!("{}", self.addr).as_bytes());
+        buf.put(host_header(&self.addr).as_bytes());
+        if let Some(authorization) = &self.authorization {
+            buf.put(&b"\r\nAuthorization: "[..]);
+            buf.put(authorization.as_bytes());
+        }
+        if let Some(metadata) = &self.metadata {
+            put_metadata(&mut buf, metadata);
+        }
         buf.put(&b"\r\n\r\n"[..]);
 
         buf.into()
@@ -181,7 +442,7 @@ impl ClientHandshake {
 
     pub fn validate_response(&self, response: &ParsedResponse<'_>) -> Result<(), InvalidHandshake> {
         if !matches!((response.version, response.code), (Some(1), Some(101))) {
-            return Err(InvalidHandshake::NonConformant);
+            return Err(InvalidHandshake::NonConformant(Requirement::Status));
         }
 
         let mut contains_headers = [0, 0];
@@ -195,38 +456,151 @@ impl ClientHandshake {
                 if self.key.encoded_hash() == h.value {
                     valid_key = true;
                 } else {
-                    return Err(InvalidHandshake::NonConformant);
+                    return Err(InvalidHandshake::NonConformant(Requirement::Accept));
                 }
             } else if h.is_any_key(&["Sec-WebSocket-Extensions", "Sec-WebSocket-Protocol"]) {
-                return Err(InvalidHandshake::NonConformant);
+                return Err(InvalidHandshake::NonConformant(Requirement::Extensions));
             }
         }
 
-        if contains_headers.iter().all(|&c| c > 0) && valid_key {
-            Ok(())
+        let requirements = [Requirement::Upgrade, Requirement::Connection];
+        if let Some(&missing) = first_missing(&contains_headers, &requirements) {
+            Err(InvalidHandshake::NonConformant(missing))
+        } else if !valid_key {
+            Err(InvalidHandshake::NonConformant(Requirement::Accept))
         } else {
-            Err(InvalidHandshake::NonConformant)
+            Ok(())
+        }
+    }
+}
+
+fn first_missing<'r>(counts: &[usize], requirements: &'r [Requirement]) -> Option<&'r Requirement> {
+    counts
+        .iter()
+        .zip(requirements)
+        .find(|(count, _)| **count == 0)
+        .map(|(_, requirement)| requirement)
+}
+
+pub(crate) fn authentication_challenges(response: &ParsedResponse<'_>) -> Option<Vec<String>> {
+    if response.code != Some(401) {
+        return None;
+    }
+
+    let challenges = response
+        .headers
+        .iter()
+        .map(HeaderObserver::from)
+        .filter(|h| h.is_key("WWW-Authenticate"))
+        .filter_map(|h| std::str::from_utf8(h.value).ok())
+        .map(str::to_string)
+        .collect();
+
+    Some(challenges)
+}
+
+pub(crate) fn offered_versions(response: &ParsedResponse<'_>) -> Option<Vec<u16>> {
+    if response.code != Some(426) {
+        return None;
+    }
+
+    let versions = response
+        .headers
+        .iter()
+        .map(HeaderObserver::from)
+        .filter(|h| h.is_key("Sec-WebSocket-Version"))
+        .filter_map(|h| std::str::from_utf8(h.value).ok())
+        .flat_map(|versions| versions.split(','))
+        .filter_map(|version| version.trim().parse().ok())
+        .collect();
+
+    Some(versions)
+}
+
+pub(crate) fn rejected_response(
+    response: &ParsedResponse<'_>,
+) -> Option<(u16, Vec<(String, Bytes)>)> {
+    let status = response.code.filter(|&code| code != 101)?;
+    let headers = response
+        .headers
+        .iter()
+        .map(|h| (h.name.to_string(), Bytes::copy_from_slice(h.value)))
+        .collect();
+
+    Some((status, headers))
+}
+
+fn put_metadata(buf: &mut BytesMut, metadata: &[u8]) {
+    buf.put(&b"\r\n"[..]);
+    buf.put(METADATA_HEADER.as_bytes());
+    buf.put(&b": "[..]);
+    buf.put(metadata);
+}
+
+fn check_duplicates(
+    request: &ParsedRequest<'_>,
+    compliance: Compliance,
+) -> Result<(), InvalidHandshake> {
+    for name in UNIQUE_HEADERS {
+        let mut values = request
+            .0
+            .headers
+            .iter()
+            .filter(|h| h.name.eq_ignore_ascii_case(name))
+            .map(|h| h.value);
+        let Some(first) = values.next() else {
+            continue;
+        };
+        let mut duplicates = values.peekable();
+        if duplicates.peek().is_none() {
+            continue;
         }
+
+        let conflicting = duplicates.any(|value| value != first);
+        if conflicting || compliance == Compliance::Strict {
+            return Err(InvalidHandshake::DuplicateHeader(name));
+        }
+
+        log::warn!("accepting repeated {name} header with identical values");
     }
+
+    Ok(())
 }
 
 #[derive(Debug)]
-pub struct ServerHanshake {
+pub(crate) struct ServerHandshake {
     key: Key,
+    metadata: Option<Bytes>,
 }
 
-impl ServerHanshake {
+impl ServerHandshake {
     fn new(key: Key) -> Self {
-        Self { key }
+        Self {
+            key,
+            metadata: None,
+        }
     }
 
-    pub fn try_from_request(request: &ParsedRequest<'_>) -> Result<Self, InvalidHandshake> {
-        if !matches!(
-            (request.0.method, request.0.path, request.0.version),
-            (Some("GET"), Some("/"), Some(1))
-        ) {
-            return Err(InvalidHandshake::NonConformant);
+    pub fn with_metadata(mut self, metadata: Option<Bytes>) -> Self {
+        self.metadata = metadata;
+
+        self
+    }
+
+    pub fn try_from_request(
+        request: &ParsedRequest<'_>,
+        compliance: Compliance,
+    ) -> Result<Self, InvalidHandshake> {
+        if request.0.method != Some("GET") {
+            return Err(InvalidHandshake::Method);
+        }
+        if request.0.version != Some(1) {
+            return Err(InvalidHandshake::Version);
+        }
+        if !request.0.path.is_some_and(is_origin_form) {
+            return Err(InvalidHandshake::Target);
         }
+        check_duplicates(request, compliance)?;
 
         let mut contains_headers = [0, 0, 0, 0, 0];
         let mut encoded_key = &[0][..];
@@ -236,7 +610,7 @@ impl ServerHanshake {
             .iter()
             .map(HeaderObserver::from)
             .for_each(|h| {
-                if h.is_key("Host") {
+                if h.is_key("Host") && is_valid_host(h.value) {
                     contains_headers[0] += 1;
                 } else if h.is("Upgrade", b"websocket") {
                     contains_headers[1] += 1;
@@ -250,10 +624,16 @@ impl ServerHanshake {
                 }
             });
 
-        if contains_headers.iter().all(|&c| c > 0) {
-            Ok(Self::new(encoded_key.into()))
-        } else {
-            Err(InvalidHandshake::NonConformant)
+        let requirements = [
+            Requirement::Host,
+            Requirement::Upgrade,
+            Requirement::Connection,
+            Requirement::Key,
+            Requirement::Version,
+        ];
+        match first_missing(&contains_headers, &requirements) {
+            Some(&missing) => Err(InvalidHandshake::NonConformant(missing)),
+            None => Ok(Self::new(encoded_key.into())),
         }
     }
 
@@ -262,8 +642,193 @@ impl ServerHanshake {
 
         buf.put(&b"HTTP/1.1 101 Switching Protocols\r\nSec-WebSocket-Accept: "[..]);
         buf.put(self.key.encoded_hash());
-        buf.put(&b"\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\r\n"[..]);
+        buf.put(&b"\r\nUpgrade: websocket\r\nConnection: Upgrade"[..]);
+        if let Some(metadata) = &self.metadata {
+            put_metadata(&mut buf, metadata);
+        }
+        buf.put(&b"\r\n\r\n"[..]);
 
         buf.into()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use claim::{assert_matches, assert_ok};
+    use std::net::SocketAddrV6;
+
+    use super::*;
+
+    const KEY: &str = "Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==";
+
+    const HOST: &str = "Host: example.com";
+
+    const VERSION: &str = "Sec-WebSocket-Version: 13";
+
+    fn accept(headers: &[&str], compliance: Compliance) -> Result<(), InvalidHandshake> {
+        accept_request_line("GET / HTTP/1.1", headers, compliance)
+    }
+
+    fn accept_request_line(
+        request_line: &str,
+        headers: &[&str],
+        compliance: Compliance,
+    ) -> Result<(), InvalidHandshake> {
+        let raw = format!(
+            "{request_line}\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n{}\r\n\r\n",
+            headers.join("\r\n")
+        );
+        let mut headers = ParsedHeadersBuf::new();
+        let request = parse_request(raw.as_bytes(), &mut headers)?;
+
+        ServerHandshake::try_from_request(&request, compliance).map(|_| ())
+    }
+
+    #[test]
+    fn unique_headers_are_accepted() {
+        assert_ok!(accept(&[KEY, HOST, VERSION], Compliance::Strict));
+        assert_ok!(accept(&[KEY, HOST, VERSION], Compliance::Lenient));
+    }
+
+    #[test]
+    fn strict_rejects_repeated_headers() {
+        for (name, headers) in [
+            ("Sec-WebSocket-Key", [KEY, KEY, HOST, VERSION]),
+            ("Host", [KEY, HOST, HOST, VERSION]),
+            ("Sec-WebSocket-Version", [KEY, HOST, VERSION, VERSION]),
+        ] {
+            assert_matches!(
+                accept(&headers, Compliance::Strict),
+                Err(InvalidHandshake::DuplicateHeader(duplicate)) if duplicate == name
+            );
+        }
+    }
+
+    #[test]
+    fn lenient_accepts_identical_repeated_headers() {
+        for headers in [
+            [KEY, KEY, HOST, VERSION],
+            [KEY, HOST, HOST, VERSION],
+            [KEY, HOST, VERSION, VERSION],
+        ] {
+            assert_ok!(accept(&headers, Compliance::Lenient));
+        }
+    }
+
+    #[test]
+    fn lenient_rejects_conflicting_repeated_headers() {
+        for (name, headers) in [
+            (
+                "Sec-WebSocket-Key",
+                [
+                    KEY,
+                    "Sec-WebSocket-Key: AAAAAAAAAAAAAAAAAAAAAA==",
+                    HOST,
+                    VERSION,
+                ],
+            ),
+            ("Host", [KEY, HOST, "Host: example.org", VERSION]),
+            (
+                "Sec-WebSocket-Version",
+                [KEY, HOST, VERSION, "Sec-WebSocket-Version: 8"],
+            ),
+        ] {
+            assert_matches!(
+                accept(&headers, Compliance::Lenient),
+                Err(InvalidHandshake::DuplicateHeader(duplicate)) if duplicate == name
+            );
+        }
+    }
+
+    #[test]
+    fn host_header_keeps_the_ipv6_zone() {
+        let scoped = SocketAddr::V6(SocketAddrV6::new(
+            Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 1),
+            8080,
+            0,
+            1,
+        ));
+        let unscoped = SocketAddr::V6(SocketAddrV6::new(Ipv6Addr::LOCALHOST, 8080, 0, 0));
+
+        assert_eq!(host_header(&scoped), "[fe80::1%251]:8080");
+        assert_eq!(host_header(&unscoped), "[::1]:8080");
+        assert!(is_valid_host(host_header(&scoped).as_bytes()));
+    }
+
+    #[test]
+    fn origin_form_targets() {
+        for target in ["/", "/chat", "/chat?room=1&user=2", "/a%20b", "/%7Euser"] {
+            assert!(is_origin_form(target), "{target}");
+        }
+    }
+
+    #[test]
+    fn non_origin_form_targets() {
+        for target in [
+            "*",
+            "http://example.com/chat",
+            "example.com:80",
+            "chat",
+            "/chat#room",
+            "/a%2",
+            "/a%zz",
+            "/a b",
+        ] {
+            assert!(!is_origin_form(target), "{target}");
+        }
+    }
+
+    #[test]
+    fn absolute_and_asterisk_form_requests_are_rejected() {
+        for request_line in ["GET http://example.com/ HTTP/1.1", "GET * HTTP/1.1"] {
+            assert_matches!(
+                accept_request_line(request_line, &[KEY, HOST, VERSION], Compliance::Strict),
+                Err(InvalidHandshake::Target)
+            );
+        }
+    }
+
+    #[test]
+    fn method_and_version_are_checked() {
+        assert_matches!(
+            accept_request_line("POST / HTTP/1.1", &[KEY, HOST, VERSION], Compliance::Strict),
+            Err(InvalidHandshake::Method)
+        );
+        assert_matches!(
+            accept_request_line("GET / HTTP/1.0", &[KEY, HOST, VERSION], Compliance::Strict),
+            Err(InvalidHandshake::Version)
+        );
+    }
+
+    #[test]
+    fn rejections_carry_a_matching_status() {
+        for (error, expected) in [
+            (
+                InvalidHandshake::RequestLineTooLong,
+                &b"HTTP/1.1 414 URI Too Long\r\n\r\n"[..],
+            ),
+            (
+                InvalidHandshake::HeadTooLarge,
+                b"HTTP/1.1 431 Request Header Fields Too Large\r\n\r\n",
+            ),
+            (
+                InvalidHandshake::Method,
+                b"HTTP/1.1 405 Method Not Allowed\r\nAllow: GET\r\n\r\n",
+            ),
+            (
+                InvalidHandshake::Version,
+                b"HTTP/1.1 505 HTTP Version Not Supported\r\n\r\n",
+            ),
+            (
+                InvalidHandshake::NonConformant(Requirement::Version),
+                b"HTTP/1.1 426 Upgrade Required\r\nSec-WebSocket-Version: 13\r\n\r\n",
+            ),
+            (
+                InvalidHandshake::Target,
+                b"HTTP/1.1 400 Bad Request\r\n\r\n",
+            ),
+        ] {
+            assert_eq!(raw_rejection_response(&error), expected);
+        }
+    }
+}
@@ -0,0 +1,74 @@
+use std::net::SocketAddr;
+
+use bytes::Bytes;
+
+use crate::{connection::WebSocketConfig, error::InvalidHandshake, info::HandshakeInfo};
+
+use super::{ClientHandshake, ParsedHeadersBuf, parse_response, response_info};
+
+/// Client side of the opening handshake, decoupled from any transport.
+///
+/// 1. Configure the request with [`Builder::new`] and the `with_*`
+///    methods.
+/// 2. Write [`Builder::raw_request`] to the server. Keep the builder
+///    around: it holds the key the response is checked against.
+/// 3. Read the response head, up to and including the blank line that
+///    ends it, and pass it to [`Builder::validate_response`]. Once it
+///    succeeds, the transport carries WebSocket frames.
+#[derive(Debug)]
+pub struct Builder {
+    handshake: ClientHandshake,
+}
+
+impl Builder {
+    /// Starts a request for `path`, with `addr` sent as the `Host` header.
+    pub fn new(addr: SocketAddr, path: &str) -> Self {
+        let handshake = ClientHandshake::new(addr, path);
+
+        Self { handshake }
+    }
+
+    /// Applies the metadata and the preferred protocol version from
+    /// `config`, as `gateway::connect_with_config` does. Retrying with
+    /// another version after a `426 Upgrade Required` is up to the caller.
+    pub fn with_config(mut self, config: &WebSocketConfig) -> Self {
+        let version = config.protocol_versions()[0];
+        self.handshake = self
+            .handshake
+            .with_metadata(config.metadata_header())
+            .with_version(version);
+
+        self
+    }
+
+    pub fn with_authorization(mut self, authorization: impl Into<String>) -> Self {
+        self.handshake = self.handshake.with_authorization(authorization.into());
+
+        self
+    }
+
+    pub fn with_version(mut self, version: u16) -> Self {
+        self.handshake = self.handshake.with_version(version);
+
+        self
+    }
+
+    /// Raw upgrade request to write to the server.
+    pub fn raw_request(&self) -> Bytes {
+        self.handshake.raw_request()
+    }
+
+    /// Checks the server's raw response head against the request that was
+    /// sent. Only a `101 Switching Protocols` response passes.
+    pub fn validate_response(&self, raw: &[u8]) -> Result<HandshakeInfo, InvalidHandshake> {
+        let mut headers = ParsedHeadersBuf::new();
+        let response = parse_response(raw, &mut headers)?;
+        self.handshake.validate_response(&response)?;
+
+        Ok(response_info(
+            &self.handshake.path,
+            self.handshake.version,
+            &response,
+        ))
+    }
+}
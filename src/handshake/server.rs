@@ -0,0 +1,56 @@
+use bytes::Bytes;
+
+use crate::{connection::WebSocketConfig, error::InvalidHandshake, info::HandshakeInfo};
+
+use super::{ParsedHeadersBuf, ServerHandshake, parse_request, raw_rejection_response};
+
+/// Server side of the opening handshake, decoupled from any transport.
+///
+/// 1. Read the request head from the peer, up to and including the
+///    blank line that ends it.
+/// 2. Pass it to [`Responder::from_request`]. On error, write
+///    [`Responder::rejection`] back to the peer and drop the connection.
+/// 3. Optionally inspect the request through [`Responder::info`], e.g.
+///    to authenticate it.
+/// 4. Write the bytes returned by [`Responder::into_raw_response`]. From
+///    then on the transport carries WebSocket frames.
+#[derive(Debug)]
+pub struct Responder {
+    handshake: ServerHandshake,
+    info: HandshakeInfo,
+}
+
+impl Responder {
+    /// Validates a raw upgrade request head.
+    ///
+    /// `config` decides how strictly the request is checked and which
+    /// metadata is sent back, as in `gateway::accept_with_config`.
+    pub fn from_request(raw: &[u8], config: &WebSocketConfig) -> Result<Self, InvalidHandshake> {
+        let mut headers = ParsedHeadersBuf::new();
+        let request = parse_request(raw, &mut headers)?;
+        let handshake = ServerHandshake::try_from_request(&request, config.compliance())?
+            .with_metadata(config.metadata_header());
+        let info = HandshakeInfo::from(&request);
+
+        Ok(Self { handshake, info })
+    }
+
+    /// Raw HTTP response that rejects a request refused with `error`.
+    pub fn rejection(error: &InvalidHandshake) -> Bytes {
+        raw_rejection_response(error)
+    }
+
+    pub fn info(&self) -> &HandshakeInfo {
+        &self.info
+    }
+
+    pub fn info_mut(&mut self) -> &mut HandshakeInfo {
+        &mut self.info
+    }
+
+    /// Raw `101 Switching Protocols` response to write back to the peer,
+    /// along with the details of the accepted request.
+    pub fn into_raw_response(self) -> (Bytes, HandshakeInfo) {
+        (self.handshake.into_raw_response(), self.info)
+    }
+}
@@ -0,0 +1,239 @@
+use std::borrow::Cow;
+
+use bytes::Bytes;
+
+use crate::{extensions::Extensions, handshake::METADATA_HEADER};
+
+#[derive(Debug)]
+pub struct HandshakeInfo {
+    pub(crate) path: String,
+    pub(crate) version: u16,
+    pub(crate) headers: Vec<(String, Bytes)>,
+    pub(crate) extensions: Extensions,
+}
+
+#[derive(Debug)]
+pub struct HttpExchange {
+    pub(crate) request: Bytes,
+    pub(crate) response: Bytes,
+}
+
+impl HttpExchange {
+    pub fn request(&self) -> &[u8] {
+        &self.request
+    }
+
+    pub fn response(&self) -> &[u8] {
+        &self.response
+    }
+}
+
+impl HandshakeInfo {
+    pub fn path(&self) -> &str {
+        self.path
+            .split_once('?')
+            .map_or(self.path.as_str(), |(path, _)| path)
+    }
+
+    pub fn version(&self) -> u16 {
+        self.version
+    }
+
+    pub fn query(&self) -> Option<&str> {
+        self.path.split_once('?').map(|(_, query)| query)
+    }
+
+    pub fn path_segments(&self) -> impl Iterator<Item = Cow<'_, str>> {
+        self.path()
+            .split('/')
+            .filter(|segment| !segment.is_empty())
+            .map(|segment| percent_decode(segment, false))
+    }
+
+    pub fn query_params(&self) -> impl Iterator<Item = (Cow<'_, str>, Cow<'_, str>)> {
+        self.query()
+            .into_iter()
+            .flat_map(|query| query.split('&'))
+            .filter(|param| !param.is_empty())
+            .map(|param| {
+                let (key, value) = param.split_once('=').unwrap_or((param, ""));
+                (percent_decode(key, true), percent_decode(value, true))
+            })
+    }
+
+    pub fn query_param(&self, name: &str) -> Option<Cow<'_, str>> {
+        self.query_params()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value)
+    }
+
+    pub(crate) fn remove_query_param(&mut self, name: &str) {
+        let Some((path, query)) = self.path.split_once('?') else {
+            return;
+        };
+
+        let query = query
+            .split('&')
+            .filter(|param| {
+                let key = param.split_once('=').map_or(*param, |(key, _)| key);
+
+                percent_decode(key, true) != name
+            })
+            .collect::<Vec<_>>()
+            .join("&");
+
+        self.path = if query.is_empty() {
+            path.to_string()
+        } else {
+            format!("{path}?{query}")
+        };
+    }
+
+    pub fn headers(&self) -> impl Iterator<Item = (&str, &[u8])> {
+        self.headers
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_ref()))
+    }
+
+    pub fn header(&self, name: &str) -> Option<&[u8]> {
+        self.headers()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value)
+    }
+
+    pub fn cookie(&self, name: &str) -> Option<&str> {
+        self.headers()
+            .filter(|(key, _)| key.eq_ignore_ascii_case("Cookie"))
+            .filter_map(|(_, value)| std::str::from_utf8(value).ok())
+            .flat_map(|cookies| cookies.split(';'))
+            .filter_map(|cookie| cookie.trim().split_once('='))
+            .find(|(key, _)| *key == name)
+            .map(|(_, value)| value)
+    }
+
+    pub fn metadata(&self) -> impl Iterator<Item = (Cow<'_, str>, Cow<'_, str>)> {
+        self.headers()
+            .filter(|(key, _)| key.eq_ignore_ascii_case(METADATA_HEADER))
+            .filter_map(|(_, value)| std::str::from_utf8(value).ok())
+            .flat_map(|entries| entries.split(';'))
+            .filter_map(|entry| entry.trim().split_once('='))
+            .map(|(key, value)| (percent_decode(key, false), percent_decode(value, false)))
+    }
+
+    pub fn metadata_value(&self, name: &str) -> Option<Cow<'_, str>> {
+        self.metadata()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value)
+    }
+
+    pub fn extensions(&self) -> &Extensions {
+        &self.extensions
+    }
+
+    pub fn extensions_mut(&mut self) -> &mut Extensions {
+        &mut self.extensions
+    }
+}
+
+pub(crate) fn percent_decode(raw: &str, plus_as_space: bool) -> Cow<'_, str> {
+    if !(raw.contains('%') || plus_as_space && raw.contains('+')) {
+        return Cow::Borrowed(raw);
+    }
+
+    let bytes = raw.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' => {
+                let high = bytes.get(i + 1).copied().and_then(hex_value);
+                let low = bytes.get(i + 2).copied().and_then(hex_value);
+                if let (Some(high), Some(low)) = (high, low) {
+                    decoded.push(high << 4 | low);
+                    i += 3;
+                    continue;
+                }
+                decoded.push(b'%');
+            }
+            b'+' if plus_as_space => decoded.push(b' '),
+            octet => decoded.push(octet),
+        }
+        i += 1;
+    }
+
+    Cow::Owned(String::from_utf8_lossy(&decoded).into_owned())
+}
+
+pub(crate) fn percent_encode(raw: &str) -> Cow<'_, str> {
+    let is_unreserved = |octet: u8| octet.is_ascii_alphanumeric() || b"-._~".contains(&octet);
+
+    if raw.bytes().all(is_unreserved) {
+        return Cow::Borrowed(raw);
+    }
+
+    let mut encoded = String::with_capacity(raw.len() * 3);
+    for octet in raw.bytes() {
+        if is_unreserved(octet) {
+            encoded.push(octet as char);
+        } else {
+            encoded.push_str(&format!("%{octet:02X}"));
+        }
+    }
+
+    Cow::Owned(encoded)
+}
+
+fn hex_value(octet: u8) -> Option<u8> {
+    match octet {
+        b'0'..=b'9' => Some(octet - b'0'),
+        b'a'..=b'f' => Some(octet - b'a' + 10),
+        b'A'..=b'F' => Some(octet - b'A' + 10),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_escapes() {
+        assert_eq!(percent_decode("a%20b%2Fc%7e", false), "a b/c~");
+        assert_eq!(percent_decode("caf%C3%A9", false), "café");
+    }
+
+    #[test]
+    fn keeps_malformed_escapes() {
+        for (raw, decoded) in [
+            ("%", "%"),
+            ("100%", "100%"),
+            ("%2", "%2"),
+            ("a%2", "a%2"),
+            ("%zz", "%zz"),
+            ("%2g", "%2g"),
+            ("%%41", "%A"),
+            ("%4%41", "%4A"),
+        ] {
+            assert_eq!(percent_decode(raw, false), decoded, "{raw}");
+        }
+    }
+
+    #[test]
+    fn replaces_invalid_utf8() {
+        assert_eq!(percent_decode("a%FFb", false), "a\u{FFFD}b");
+        assert_eq!(percent_decode("%C3", false), "\u{FFFD}");
+    }
+
+    #[test]
+    fn decodes_plus_only_when_asked() {
+        assert_eq!(percent_decode("a+b%2B", true), "a b+");
+        assert_eq!(percent_decode("a+b", false), "a+b");
+    }
+
+    #[test]
+    fn round_trips_encoded_values() {
+        for raw in ["plain", "a b&c=d", "100%", "café", "+;/?"] {
+            assert_eq!(percent_decode(&percent_encode(raw), true), raw);
+        }
+    }
+}
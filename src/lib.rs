@@ -1,7 +1,16 @@
 #![allow(private_interfaces, private_bounds)]
 
+pub mod auth;
 pub mod connection;
 pub mod error;
+pub mod extensions;
 pub mod gateway;
-pub(crate) mod handshake;
+pub mod handshake;
+pub mod info;
+#[cfg(feature = "loadtest")]
+pub mod loadtest;
 pub mod message;
+pub mod rpc;
+pub mod sharded;
+#[cfg(feature = "testing")]
+pub mod testing;
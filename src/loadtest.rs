@@ -0,0 +1,163 @@
+use std::{
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+use tokio::task::JoinSet;
+
+use crate::{
+    error::WebSocketError,
+    gateway,
+    message::{Binary, Message, Text},
+};
+
+/// Measures round trips against an echo peer.
+///
+/// Each client sends a message and waits for a reply before sending the
+/// next one, so the server at the target address must answer every
+/// message with exactly one message of its own. Clients run as separate
+/// tasks, spread across the runtime's worker threads.
+#[derive(Debug, Clone)]
+pub struct LoadTest {
+    clients: usize,
+    messages_per_client: usize,
+    message_size: usize,
+    interval: Option<Duration>,
+    binary: bool,
+}
+
+impl LoadTest {
+    pub fn new(clients: usize) -> Self {
+        Self {
+            clients,
+            messages_per_client: 100,
+            message_size: 64,
+            interval: None,
+            binary: false,
+        }
+    }
+
+    pub fn messages_per_client(mut self, messages: usize) -> Self {
+        self.messages_per_client = messages;
+
+        self
+    }
+
+    pub fn message_size(mut self, size: usize) -> Self {
+        self.message_size = size;
+
+        self
+    }
+
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = Some(interval);
+
+        self
+    }
+
+    pub fn binary(mut self, binary: bool) -> Self {
+        self.binary = binary;
+
+        self
+    }
+
+    fn message(&self) -> Message {
+        if self.binary {
+            Binary::from(vec![0xAB; self.message_size]).into()
+        } else {
+            Text::from("x".repeat(self.message_size)).into()
+        }
+    }
+
+    async fn drive_client(&self, addr: SocketAddr) -> Result<Vec<Duration>, WebSocketError> {
+        let connection = gateway::connect(addr).await?;
+        let mut latencies = Vec::with_capacity(self.messages_per_client);
+
+        for _ in 0..self.messages_per_client {
+            let start = Instant::now();
+            connection.send(self.message()).await?;
+            connection.receive().await?;
+            latencies.push(start.elapsed());
+
+            if let Some(interval) = self.interval {
+                tokio::time::sleep(interval).await;
+            }
+        }
+
+        connection.stop().await;
+
+        Ok(latencies)
+    }
+
+    pub async fn run(&self, addr: SocketAddr) -> Result<Report, WebSocketError> {
+        let start = Instant::now();
+        let mut clients = JoinSet::new();
+        for _ in 0..self.clients {
+            let load_test = self.clone();
+            clients.spawn(async move { load_test.drive_client(addr).await });
+        }
+
+        let mut latencies = Vec::with_capacity(self.clients * self.messages_per_client);
+        while let Some(client) = clients.join_next().await {
+            match client {
+                Ok(client_latencies) => latencies.extend(client_latencies?),
+                Err(e) => std::panic::resume_unwind(e.into_panic()),
+            }
+        }
+        let elapsed = start.elapsed();
+
+        latencies.sort_unstable();
+
+        Ok(Report {
+            clients: self.clients,
+            bytes: latencies.len() * self.message_size,
+            latencies,
+            elapsed,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Report {
+    clients: usize,
+    bytes: usize,
+    latencies: Vec<Duration>,
+    elapsed: Duration,
+}
+
+impl Report {
+    pub fn clients(&self) -> usize {
+        self.clients
+    }
+
+    pub fn messages(&self) -> usize {
+        self.latencies.len()
+    }
+
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+
+    pub fn messages_per_second(&self) -> f64 {
+        self.messages() as f64 / self.elapsed.as_secs_f64()
+    }
+
+    pub fn bytes_per_second(&self) -> f64 {
+        self.bytes as f64 / self.elapsed.as_secs_f64()
+    }
+
+    pub fn percentile(&self, percentile: f64) -> Duration {
+        if self.latencies.is_empty() {
+            return Duration::ZERO;
+        }
+
+        let rank = (percentile.clamp(0.0, 100.0) / 100.0 * (self.latencies.len() - 1) as f64)
+            .round() as usize;
+
+        self.latencies[rank]
+    }
+}
@@ -1,6 +1,6 @@
 use std::ops::Deref;
 
-use bytes::Bytes;
+use bytes::{Buf, BufMut, Bytes, BytesMut};
 
 #[derive(Debug)]
 pub struct Text(pub(crate) Bytes);
@@ -9,6 +9,28 @@ impl Text {
     pub fn as_str(&self) -> &str {
         unsafe { std::str::from_utf8_unchecked(&self.0) }
     }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    pub fn into_bytes(self) -> Bytes {
+        self.0
+    }
+
+    pub fn from_buf(mut buf: impl Buf) -> Result<Self, std::str::Utf8Error> {
+        buf.copy_to_bytes(buf.remaining()).try_into()
+    }
+}
+
+impl TryFrom<Bytes> for Text {
+    type Error = std::str::Utf8Error;
+
+    fn try_from(raw: Bytes) -> Result<Self, Self::Error> {
+        std::str::from_utf8(&raw)?;
+
+        Ok(Text(raw))
+    }
 }
 
 impl TryFrom<&[u8]> for Text {
@@ -58,6 +80,38 @@ impl Binary {
     pub fn as_bytes(&self) -> &[u8] {
         &self.0
     }
+
+    pub fn into_bytes(self) -> Bytes {
+        self.0
+    }
+
+    pub fn from_buf(mut buf: impl Buf) -> Self {
+        Binary(buf.copy_to_bytes(buf.remaining()))
+    }
+}
+
+impl From<Bytes> for Binary {
+    fn from(raw: Bytes) -> Self {
+        Binary(raw)
+    }
+}
+
+impl Buf for Binary {
+    fn remaining(&self) -> usize {
+        self.0.remaining()
+    }
+
+    fn chunk(&self) -> &[u8] {
+        self.0.chunk()
+    }
+
+    fn advance(&mut self, cnt: usize) {
+        self.0.advance(cnt)
+    }
+
+    fn copy_to_bytes(&mut self, len: usize) -> Bytes {
+        self.0.copy_to_bytes(len)
+    }
 }
 
 impl From<&[u8]> for Binary {
@@ -81,11 +135,38 @@ impl Deref for Binary {
 }
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Message {
     Text(Text),
     Binary(Binary),
 }
 
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Envelope {
+    pub(crate) message: Message,
+    pub(crate) fragments: usize,
+    pub(crate) wire_size: usize,
+}
+
+impl Envelope {
+    pub fn message(&self) -> &Message {
+        &self.message
+    }
+
+    pub fn into_message(self) -> Message {
+        self.message
+    }
+
+    pub fn fragments(&self) -> usize {
+        self.fragments
+    }
+
+    pub fn wire_size(&self) -> usize {
+        self.wire_size
+    }
+}
+
 impl Message {
     pub fn size(&self) -> usize {
         match self {
@@ -117,6 +198,94 @@ impl Message {
     }
 }
 
+#[derive(Debug)]
+pub struct MessageBuilder {
+    buf: BytesMut,
+    text: bool,
+    checked: bool,
+}
+
+impl MessageBuilder {
+    fn new(text: bool, capacity: usize) -> Self {
+        Self {
+            buf: BytesMut::with_capacity(capacity),
+            text,
+            checked: true,
+        }
+    }
+
+    pub fn text() -> Self {
+        Self::new(true, 0)
+    }
+
+    pub fn binary() -> Self {
+        Self::new(false, 0)
+    }
+
+    pub fn text_with_capacity(capacity: usize) -> Self {
+        Self::new(true, capacity)
+    }
+
+    pub fn binary_with_capacity(capacity: usize) -> Self {
+        Self::new(false, capacity)
+    }
+
+    pub fn push_str(&mut self, text: &str) -> &mut Self {
+        self.buf.put(text.as_bytes());
+
+        self
+    }
+
+    pub fn put_bytes(&mut self, raw: &[u8]) -> &mut Self {
+        self.buf.put(raw);
+        self.checked = false;
+
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.buf.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+
+    pub fn build(self) -> Result<Message, std::str::Utf8Error> {
+        let raw = self.buf.freeze();
+
+        if !self.text {
+            return Ok(Message::Binary(Binary(raw)));
+        }
+
+        if !self.checked {
+            std::str::from_utf8(&raw)?;
+        }
+
+        Ok(Message::Text(Text(raw)))
+    }
+}
+
+impl std::fmt::Write for MessageBuilder {
+    fn write_str(&mut self, text: &str) -> std::fmt::Result {
+        self.push_str(text);
+
+        Ok(())
+    }
+}
+
+impl std::io::Write for MessageBuilder {
+    fn write(&mut self, raw: &[u8]) -> std::io::Result<usize> {
+        self.put_bytes(raw);
+
+        Ok(raw.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
 impl From<Text> for Message {
     fn from(text: Text) -> Self {
         Message::Text(text)
@@ -128,3 +297,31 @@ impl From<Binary> for Message {
         Message::Binary(binary)
     }
 }
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for Text {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Text {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer).map(Text::from)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for Binary {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(self.as_bytes())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Binary {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Vec::<u8>::deserialize(deserializer).map(Binary::from)
+    }
+}
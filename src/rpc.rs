@@ -0,0 +1,184 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use tokio::{sync::oneshot, task::JoinHandle};
+
+use crate::{
+    connection::Connection,
+    error::WebSocketError,
+    message::{Binary, Message},
+};
+
+const REQUEST: u8 = 0;
+const RESPONSE: u8 = 1;
+const ENVELOPE_HEADER_SIZE: usize = 9;
+
+type Pending = Arc<Mutex<HashMap<u64, oneshot::Sender<Binary>>>>;
+
+#[derive(Debug)]
+pub struct Request {
+    id: u64,
+    payload: Binary,
+}
+
+impl Request {
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn payload(&self) -> &Binary {
+        &self.payload
+    }
+
+    pub fn into_payload(self) -> Binary {
+        self.payload
+    }
+}
+
+fn encode(kind: u8, id: u64, payload: Binary) -> Message {
+    let payload = payload.into_bytes();
+    let mut envelope = BytesMut::with_capacity(ENVELOPE_HEADER_SIZE + payload.len());
+    envelope.put_u8(kind);
+    envelope.put_u64(id);
+    envelope.put(payload);
+
+    Message::Binary(Binary::from(envelope.freeze()))
+}
+
+fn decode(mut envelope: Bytes) -> Option<(u8, u64, Binary)> {
+    if envelope.len() < ENVELOPE_HEADER_SIZE {
+        return None;
+    }
+
+    let kind = envelope.get_u8();
+    let id = envelope.get_u64();
+
+    Some((kind, id, Binary::from(envelope)))
+}
+
+pub struct RpcConnection {
+    connection: Arc<Connection>,
+    next_id: AtomicU64,
+    pending: Pending,
+    request_rx: flume::Receiver<Request>,
+    forwarder: JoinHandle<()>,
+}
+
+impl RpcConnection {
+    pub fn new(connection: Connection) -> Self {
+        let connection = Arc::new(connection);
+        let pending = Pending::default();
+        let (request_tx, request_rx) = flume::unbounded();
+
+        let forwarder = {
+            let connection = connection.clone();
+            let pending = pending.clone();
+
+            tokio::spawn(async move {
+                while let Ok(message) = connection.receive().await {
+                    let Message::Binary(binary) = message else {
+                        log::warn!("ignoring text message on rpc connection");
+                        continue;
+                    };
+
+                    match decode(binary.into_bytes()) {
+                        Some((REQUEST, id, payload)) => {
+                            let request = Request { id, payload };
+
+                            if request_tx.send_async(request).await.is_err() {
+                                break;
+                            }
+                        }
+                        Some((RESPONSE, id, payload)) => {
+                            let reply_tx = pending.lock().unwrap().remove(&id);
+
+                            if let Some(reply_tx) = reply_tx {
+                                let _ = reply_tx.send(payload);
+                            }
+                        }
+                        _ => log::warn!("ignoring malformed envelope on rpc connection"),
+                    }
+                }
+
+                pending.lock().unwrap().clear();
+            })
+        };
+
+        Self {
+            connection,
+            next_id: AtomicU64::new(0),
+            pending,
+            request_rx,
+            forwarder,
+        }
+    }
+
+    pub fn connection(&self) -> &Connection {
+        &self.connection
+    }
+
+    pub async fn call(&self, payload: Binary, timeout: Duration) -> Result<Binary, WebSocketError> {
+        if self.forwarder.is_finished() {
+            return Err(WebSocketError::ConnectionClosed);
+        }
+
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (reply_tx, reply_rx) = oneshot::channel();
+        self.pending.lock().unwrap().insert(id, reply_tx);
+
+        if let Err(e) = self.connection.send(encode(REQUEST, id, payload)).await {
+            self.pending.lock().unwrap().remove(&id);
+
+            return Err(e);
+        }
+
+        match tokio::time::timeout(timeout, reply_rx).await {
+            Ok(Ok(payload)) => Ok(payload),
+            Ok(Err(_)) => Err(WebSocketError::ConnectionClosed),
+            Err(_) => {
+                self.pending.lock().unwrap().remove(&id);
+
+                Err(WebSocketError::CallTimeout)
+            }
+        }
+    }
+
+    pub async fn receive(&self) -> Result<Request, WebSocketError> {
+        self.request_rx
+            .recv_async()
+            .await
+            .map_err(|_| WebSocketError::ConnectionClosed)
+    }
+
+    pub async fn respond(&self, id: u64, payload: Binary) -> Result<(), WebSocketError> {
+        self.connection
+            .send(encode(RESPONSE, id, payload))
+            .await
+            .map(|_| ())
+    }
+
+    pub async fn stop(mut self) {
+        self.forwarder.abort();
+        let _ = (&mut self.forwarder).await;
+
+        let connection = self.connection.clone();
+        drop(self);
+
+        if let Ok(connection) = Arc::try_unwrap(connection) {
+            connection.stop().await;
+        }
+    }
+}
+
+impl Drop for RpcConnection {
+    fn drop(&mut self) {
+        self.forwarder.abort();
+    }
+}
@@ -0,0 +1,132 @@
+use std::{
+    hash::{DefaultHasher, Hash, Hasher},
+    io,
+    net::SocketAddr,
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+};
+
+use futures_util::future::try_join_all;
+use tokio::task::JoinHandle;
+
+use crate::{
+    connection::{Connection, SendHandle, WebSocketConfig},
+    error::WebSocketError,
+    gateway,
+    message::Message,
+};
+
+pub struct ShardedClient {
+    connections: Vec<Arc<Connection>>,
+    next: AtomicUsize,
+    receive_rx: flume::Receiver<Message>,
+    forwarders: Vec<JoinHandle<()>>,
+}
+
+impl ShardedClient {
+    pub async fn connect(
+        addr: SocketAddr,
+        path: &str,
+        shards: usize,
+        config: WebSocketConfig,
+    ) -> Result<Self, WebSocketError> {
+        if shards == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "a sharded client needs at least one shard",
+            )
+            .into());
+        }
+
+        let connections = try_join_all(
+            (0..shards).map(|_| gateway::connect_with_config(addr, path, config.clone())),
+        )
+        .await?;
+        let connections: Vec<_> = connections.into_iter().map(Arc::new).collect();
+
+        let (receive_tx, receive_rx) = flume::unbounded();
+        let forwarders = connections
+            .iter()
+            .map(|connection| {
+                let connection = connection.clone();
+                let receive_tx = receive_tx.clone();
+
+                tokio::spawn(async move {
+                    while let Ok(message) = connection.receive().await {
+                        if receive_tx.send_async(message).await.is_err() {
+                            break;
+                        }
+                    }
+                })
+            })
+            .collect();
+
+        Ok(Self {
+            connections,
+            next: AtomicUsize::new(0),
+            receive_rx,
+            forwarders,
+        })
+    }
+
+    pub fn shards(&self) -> usize {
+        self.connections.len()
+    }
+
+    pub fn shard(&self, index: usize) -> &Connection {
+        &self.connections[index]
+    }
+
+    pub async fn send(&self, message: Message) -> Result<SendHandle, WebSocketError> {
+        let index = self.next.fetch_add(1, Ordering::Relaxed) % self.shards();
+
+        self.connections[index].send(message).await
+    }
+
+    pub async fn send_by_key<K: Hash>(
+        &self,
+        key: &K,
+        message: Message,
+    ) -> Result<SendHandle, WebSocketError> {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        let index = (hasher.finish() % self.shards() as u64) as usize;
+
+        self.connections[index].send(message).await
+    }
+
+    pub async fn receive(&self) -> Result<Message, WebSocketError> {
+        self.receive_rx
+            .recv_async()
+            .await
+            .map_err(|_| WebSocketError::ConnectionClosed)
+    }
+
+    pub async fn stop(mut self) {
+        for forwarder in &self.forwarders {
+            forwarder.abort();
+        }
+        for forwarder in &mut self.forwarders {
+            let _ = forwarder.await;
+        }
+
+        let connections = std::mem::take(&mut self.connections);
+        drop(self);
+
+        for connection in connections {
+            if let Ok(connection) = Arc::try_unwrap(connection) {
+                connection.stop().await;
+            }
+        }
+    }
+}
+
+impl Drop for ShardedClient {
+    fn drop(&mut self) {
+        for forwarder in &self.forwarders {
+            forwarder.abort();
+        }
+    }
+}
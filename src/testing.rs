@@ -0,0 +1,227 @@
+pub mod strategy;
+
+use std::{net::SocketAddr, time::Duration};
+
+use bytes::{BufMut, Bytes, BytesMut};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    task::JoinHandle,
+};
+
+use crate::{
+    connection::Compliance,
+    error::WebSocketError,
+    gateway::Buf,
+    handshake::{ParsedHeadersBuf, ServerHandshake, parse_request, raw_rejection_response},
+};
+
+#[derive(Debug, Clone)]
+pub struct Frame {
+    pub fin: bool,
+    pub rsv: u8,
+    pub opcode: u8,
+    pub masking_key: Option<[u8; 4]>,
+    pub payload: Bytes,
+}
+
+impl Frame {
+    pub fn new(fin: bool, opcode: u8, payload: impl Into<Bytes>) -> Self {
+        Self {
+            fin,
+            rsv: 0,
+            opcode,
+            masking_key: None,
+            payload: payload.into(),
+        }
+    }
+
+    pub fn to_bytes(&self) -> Bytes {
+        let mut buf = BytesMut::with_capacity(14 + self.payload.len());
+
+        buf.put_u8(((self.fin as u8) << 7) | ((self.rsv & 0b111) << 4) | (self.opcode & 0xF));
+        let masked = if self.masking_key.is_some() {
+            1 << 7
+        } else {
+            0
+        };
+        match self.payload.len() {
+            length @ 0..=125 => buf.put_u8(masked | length as u8),
+            length @ 126..=0xFFFF => {
+                buf.put_u8(masked | 126);
+                buf.put_u16(length as u16);
+            }
+            length => {
+                buf.put_u8(masked | 127);
+                buf.put_u64(length as u64);
+            }
+        }
+        match self.masking_key {
+            Some(masking_key) => {
+                buf.put(&masking_key[..]);
+                buf.extend(
+                    self.payload
+                        .iter()
+                        .enumerate()
+                        .map(|(i, b)| b ^ masking_key[i % 4]),
+                );
+            }
+            None => buf.put(&self.payload[..]),
+        }
+
+        buf.into()
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Step {
+    Frame(Frame),
+    Raw(Bytes),
+    Expect,
+    Delay(Duration),
+    Disconnect,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Script {
+    steps: Vec<Step>,
+}
+
+impl Script {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(mut self, frame: Frame) -> Self {
+        self.steps.push(Step::Frame(frame));
+
+        self
+    }
+
+    pub fn frame(self, fin: bool, opcode: u8, payload: impl Into<Bytes>) -> Self {
+        self.push(Frame::new(fin, opcode, payload))
+    }
+
+    pub fn text(self, text: &str) -> Self {
+        self.frame(true, 0x1, Bytes::copy_from_slice(text.as_bytes()))
+    }
+
+    pub fn binary(self, binary: &[u8]) -> Self {
+        self.frame(true, 0x2, Bytes::copy_from_slice(binary))
+    }
+
+    pub fn ping(self, payload: &[u8]) -> Self {
+        self.frame(true, 0x9, Bytes::copy_from_slice(payload))
+    }
+
+    pub fn close(self, code: u16, reason: &str) -> Self {
+        let mut payload = BytesMut::with_capacity(2 + reason.len());
+        payload.put_u16(code);
+        payload.put(reason.as_bytes());
+
+        self.frame(true, 0x8, payload)
+    }
+
+    pub fn raw(mut self, raw: impl Into<Bytes>) -> Self {
+        self.steps.push(Step::Raw(raw.into()));
+
+        self
+    }
+
+    pub fn expect(mut self) -> Self {
+        self.steps.push(Step::Expect);
+
+        self
+    }
+
+    pub fn delay(mut self, duration: Duration) -> Self {
+        self.steps.push(Step::Delay(duration));
+
+        self
+    }
+
+    pub fn disconnect(mut self) -> Self {
+        self.steps.push(Step::Disconnect);
+
+        self
+    }
+}
+
+async fn skip_frame(stream: &mut TcpStream) -> Result<(), WebSocketError> {
+    stream.read_u8().await?;
+    let octet = stream.read_u8().await?;
+    let payload_length = match octet & 0x7F {
+        126 => stream.read_u16().await? as u64,
+        127 => stream.read_u64().await?,
+        length => length as u64,
+    };
+    let masking_key_length = if octet >> 7 != 0 { 4 } else { 0 };
+
+    let mut remaining = (&mut *stream).take(masking_key_length + payload_length);
+    tokio::io::copy(&mut remaining, &mut tokio::io::sink()).await?;
+
+    Ok(())
+}
+
+async fn play(stream: TcpStream, script: Script) -> Result<(), WebSocketError> {
+    let mut buf = Buf::new(stream);
+
+    let raw_request = buf.read_raw_http().await?;
+    let mut headers = ParsedHeadersBuf::new();
+    let request = parse_request(&raw_request, &mut headers)?;
+    let raw_response = match ServerHandshake::try_from_request(&request, Compliance::Strict) {
+        Ok(handshake) => handshake.into_raw_response(),
+        Err(e) => {
+            buf.write_raw_http(&raw_rejection_response(&e)).await?;
+
+            return Err(e.into());
+        }
+    };
+    buf.write_raw_http(&raw_response).await?;
+
+    let mut stream: TcpStream = buf.into();
+    for step in script.steps {
+        match step {
+            Step::Frame(frame) => stream.write_all(&frame.to_bytes()).await?,
+            Step::Raw(raw) => stream.write_all(&raw).await?,
+            Step::Expect => skip_frame(&mut stream).await?,
+            Step::Delay(duration) => tokio::time::sleep(duration).await,
+            Step::Disconnect => return Ok(()),
+        }
+    }
+
+    stream.shutdown().await?;
+
+    Ok(())
+}
+
+#[derive(Debug)]
+pub struct MockServer {
+    addr: SocketAddr,
+    handle: JoinHandle<()>,
+}
+
+impl MockServer {
+    pub async fn start(script: Script) -> Result<Self, WebSocketError> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+
+        let handle = tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(play(stream, script.clone()));
+            }
+        });
+
+        Ok(Self { addr, handle })
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
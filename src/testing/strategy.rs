@@ -0,0 +1,164 @@
+use base64::{Engine, prelude::BASE64_STANDARD};
+use bytes::{BufMut, Bytes, BytesMut};
+use proptest::{collection::vec, option, prelude::*, sample::select};
+
+use crate::testing::Frame;
+
+const DATA_OPCODES: [u8; 2] = [0x1, 0x2];
+const PING_PONG_OPCODES: [u8; 2] = [0x9, 0xA];
+
+fn payload(max_length: usize) -> impl Strategy<Value = Bytes> {
+    vec(any::<u8>(), 0..=max_length).prop_map(Bytes::from)
+}
+
+fn masking_key(masked: bool) -> BoxedStrategy<Option<[u8; 4]>> {
+    if masked {
+        any::<[u8; 4]>().prop_map(Some).boxed()
+    } else {
+        Just(None).boxed()
+    }
+}
+
+fn close_payload() -> impl Strategy<Value = Bytes> {
+    let status = prop_oneof![1000..=1003u16, 1007..=1014u16, 3000..=4999u16];
+
+    option::of((status, "\\PC{0,30}")).prop_map(|content| {
+        let mut payload = BytesMut::new();
+        if let Some((status, reason)) = content {
+            payload.put_u16(status);
+            payload.put(reason.as_bytes());
+        }
+
+        payload.into()
+    })
+}
+
+pub fn frame(masked: bool, max_payload_length: usize) -> impl Strategy<Value = Frame> {
+    (
+        any::<bool>(),
+        0..8u8,
+        0..16u8,
+        masking_key(masked),
+        payload(max_payload_length),
+    )
+        .prop_map(|(fin, rsv, opcode, masking_key, payload)| Frame {
+            fin,
+            rsv,
+            opcode,
+            masking_key,
+            payload,
+        })
+}
+
+pub fn data_frame(masked: bool, max_payload_length: usize) -> impl Strategy<Value = Frame> {
+    (
+        select(&DATA_OPCODES[..]),
+        masking_key(masked),
+        payload(max_payload_length),
+    )
+        .prop_map(|(opcode, masking_key, payload)| Frame {
+            masking_key,
+            ..Frame::new(true, opcode, payload)
+        })
+}
+
+pub fn control_frame(masked: bool) -> impl Strategy<Value = Frame> {
+    let ping_pong = (select(&PING_PONG_OPCODES[..]), payload(125));
+    let close = close_payload().prop_map(|payload| (0x8, payload));
+
+    (prop_oneof![ping_pong, close], masking_key(masked)).prop_map(
+        |((opcode, payload), masking_key)| Frame {
+            masking_key,
+            ..Frame::new(true, opcode, payload)
+        },
+    )
+}
+
+pub fn fragmented_message(
+    masked: bool,
+    max_fragments: usize,
+    max_fragment_length: usize,
+) -> impl Strategy<Value = Vec<Frame>> {
+    let interleaved = select(&PING_PONG_OPCODES[..])
+        .prop_flat_map(move |opcode| (Just(opcode), masking_key(masked), payload(125)))
+        .prop_map(|(opcode, masking_key, payload)| Frame {
+            masking_key,
+            ..Frame::new(true, opcode, payload)
+        });
+
+    (
+        select(&DATA_OPCODES[..]),
+        vec(
+            (
+                masking_key(masked),
+                payload(max_fragment_length),
+                option::of(interleaved),
+            ),
+            1..=max_fragments.max(1),
+        ),
+    )
+        .prop_map(|(opcode, fragments)| {
+            let last = fragments.len() - 1;
+
+            fragments
+                .into_iter()
+                .enumerate()
+                .flat_map(|(i, (masking_key, payload, interleaved))| {
+                    let opcode = if i == 0 { opcode } else { 0x0 };
+                    let fragment = Frame {
+                        masking_key,
+                        ..Frame::new(i == last, opcode, payload)
+                    };
+
+                    std::iter::once(fragment).chain(interleaved.filter(|_| i != last))
+                })
+                .collect()
+        })
+}
+
+fn header_name(name: &'static str) -> impl Strategy<Value = String> {
+    vec(any::<bool>(), name.len()).prop_map(move |upper| {
+        name.chars()
+            .zip(upper)
+            .map(|(c, upper)| {
+                if upper {
+                    c.to_ascii_uppercase()
+                } else {
+                    c.to_ascii_lowercase()
+                }
+            })
+            .collect()
+    })
+}
+
+pub fn handshake_request() -> impl Strategy<Value = Bytes> {
+    (
+        (header_name("Host"), "[a-z0-9.-]{1,32}"),
+        header_name("Upgrade"),
+        header_name("Connection"),
+        (header_name("Sec-WebSocket-Key"), any::<[u8; 16]>()),
+        header_name("Sec-WebSocket-Version"),
+    )
+        .prop_map(
+            |((host, hostname), upgrade, connection, (key, nonce), version)| {
+                vec![
+                    format!("{host}: {hostname}"),
+                    format!("{upgrade}: websocket"),
+                    format!("{connection}: Upgrade"),
+                    format!("{key}: {}", BASE64_STANDARD.encode(nonce)),
+                    format!("{version}: 13"),
+                ]
+            },
+        )
+        .prop_shuffle()
+        .prop_map(|lines| {
+            let mut raw = String::from("GET / HTTP/1.1\r\n");
+            for line in lines {
+                raw.push_str(&line);
+                raw.push_str("\r\n");
+            }
+            raw.push_str("\r\n");
+
+            raw.into()
+        })
+}
@@ -0,0 +1,87 @@
+use std::sync::{Arc, Mutex};
+
+use bytes::Bytes;
+use claim::{assert_matches, assert_ok};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+};
+use websocket::{
+    auth::{QueryTokenAuthenticator, Rejection},
+    connection::WebSocketConfig,
+    error::WebSocketError,
+    gateway,
+};
+
+#[tokio::test]
+async fn query_token_is_redacted_from_logged_exchange() {
+    let listener = assert_ok!(TcpListener::bind("127.0.0.1:0").await);
+    let addr = assert_ok!(listener.local_addr());
+
+    let logged = Arc::new(Mutex::new(Vec::new()));
+    let config = {
+        let logged = logged.clone();
+
+        WebSocketConfig::new().with_exchange_hook(move |exchange| {
+            logged
+                .lock()
+                .unwrap()
+                .push(Bytes::copy_from_slice(exchange.request()));
+        })
+    };
+
+    let server = tokio::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let authenticator = QueryTokenAuthenticator::new("token", |token: String| async move {
+            if token == "SECRET" {
+                Ok(())
+            } else {
+                Err(Rejection::new())
+            }
+        });
+
+        gateway::accept_with_config(stream, &authenticator, config)
+            .await
+            .map(|connection| connection.handshake_info().path().to_string())
+    });
+
+    assert_ok!(gateway::connect_with_path(addr, "/chat?room=1&token=SECRET").await);
+    let path = assert_ok!(assert_ok!(server.await));
+    assert_eq!(path, "/chat");
+
+    let logged = logged.lock().unwrap();
+    assert_eq!(logged.len(), 1);
+
+    let request = String::from_utf8_lossy(&logged[0]);
+    assert!(!request.contains("SECRET"));
+    assert!(request.starts_with("GET /chat?room=1&token=[redacted] HTTP/1.1\r\n"));
+}
+
+#[tokio::test]
+async fn unanswerable_challenge_keeps_the_rejection() {
+    let listener = assert_ok!(TcpListener::bind("127.0.0.1:0").await);
+    let addr = assert_ok!(listener.local_addr());
+
+    tokio::spawn(async move {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut request = [0; 1024];
+        let _ = stream.read(&mut request).await.unwrap();
+        stream
+            .write_all(
+                b"HTTP/1.1 401 Unauthorized\r\n\
+                  WWW-Authenticate: Basic realm=\"chat\"\r\n\
+                  Content-Length: 13\r\n\r\n\
+                  token expired",
+            )
+            .await
+            .unwrap();
+    });
+
+    let Err(error) = gateway::connect(addr).await else {
+        panic!("handshake should have been rejected");
+    };
+    assert_matches!(
+        error,
+        WebSocketError::HandshakeRejected { status: 401, body, .. } if &body[..] == b"token expired"
+    );
+}
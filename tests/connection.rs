@@ -0,0 +1,300 @@
+use std::{net::SocketAddr, time::Duration};
+
+use bytes::Buf;
+use claim::{assert_matches, assert_ok};
+use tokio::{net::TcpListener, sync::oneshot, task::JoinHandle};
+use websocket::{
+    connection::{Connection, DropPolicy, EventKind, Limits, State, StatusCode, WebSocketConfig},
+    error::{InvalidFrame, WebSocketError},
+    gateway,
+    message::{Binary, Message, Text},
+};
+
+async fn collecting_server() -> (SocketAddr, JoinHandle<(Vec<String>, WebSocketError)>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let server = tokio::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let connection = gateway::accept(stream).await.unwrap();
+
+        let mut received = Vec::new();
+        let closed = loop {
+            match connection.receive().await {
+                Ok(Message::Text(text)) => received.push(text.to_string()),
+                Ok(message) => panic!("unexpected message: {message:?}"),
+                Err(e) => break e,
+            }
+        };
+
+        (received, closed)
+    });
+
+    (addr, server)
+}
+
+fn numbered(count: usize) -> Vec<String> {
+    (0..count).map(|i| i.to_string()).collect()
+}
+
+async fn send_numbered(connection: &Connection, count: usize) {
+    for text in numbered(count) {
+        assert_ok!(connection.send(Message::from(Text::from(text))).await);
+    }
+}
+
+#[tokio::test]
+async fn stop_flushes_queued_messages_before_closing() {
+    let (addr, server) = collecting_server().await;
+
+    let connection = assert_ok!(gateway::connect(addr).await);
+    send_numbered(&connection, 32).await;
+    connection.stop().await;
+
+    let (received, closed) = assert_ok!(server.await);
+    assert_eq!(received, numbered(32));
+    assert_matches!(closed, WebSocketError::ConnectionClosed);
+}
+
+#[tokio::test]
+async fn close_flushes_queued_messages_before_closing() {
+    let (addr, server) = collecting_server().await;
+
+    let connection = assert_ok!(gateway::connect(addr).await);
+    send_numbered(&connection, 32).await;
+    let code = assert_ok!(connection.close(StatusCode::NormalClosure, None).await);
+    assert_eq!(code, Some(StatusCode::NormalClosure));
+
+    let (received, closed) = assert_ok!(server.await);
+    assert_eq!(received, numbered(32));
+    assert_matches!(closed, WebSocketError::ConnectionClosed);
+}
+
+#[tokio::test]
+async fn drop_flushes_queued_messages_before_closing() {
+    let (addr, server) = collecting_server().await;
+
+    let connection = assert_ok!(gateway::connect(addr).await);
+    send_numbered(&connection, 32).await;
+    drop(connection);
+
+    let (received, closed) = assert_ok!(server.await);
+    assert_eq!(received, numbered(32));
+    assert_matches!(closed, WebSocketError::ConnectionClosed);
+}
+
+#[tokio::test]
+async fn paused_connection_holds_messages_until_resumed() {
+    let listener = assert_ok!(TcpListener::bind("127.0.0.1:0").await);
+    let addr = assert_ok!(listener.local_addr());
+    let (paused_tx, paused_rx) = oneshot::channel();
+
+    let server = tokio::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let connection = gateway::accept(stream).await.unwrap();
+
+        paused_rx.await.unwrap();
+        connection
+            .send(Message::from(Text::from("held")))
+            .await
+            .unwrap();
+
+        connection.receive().await
+    });
+
+    let connection = assert_ok!(gateway::connect(addr).await);
+    connection.pause_receiving();
+    assert_ok!(paused_tx.send(()));
+
+    let held = tokio::time::timeout(Duration::from_millis(100), connection.receive()).await;
+    assert!(held.is_err());
+
+    connection.resume_receiving();
+    assert_matches!(connection.receive().await, Ok(Message::Text(text)) if &*text == "held");
+
+    connection.stop().await;
+    assert_matches!(
+        assert_ok!(server.await),
+        Err(WebSocketError::ConnectionClosed)
+    );
+}
+
+#[tokio::test]
+async fn paused_connection_is_not_idle() {
+    let (addr, _server) = collecting_server().await;
+
+    let mut connection = assert_ok!(gateway::connect(addr).await);
+    let events_rx = assert_ok!(connection.observe_events().await);
+    connection.set_limits(Limits::new().with_idle_timeout(Duration::from_millis(100)));
+    connection.pause_receiving();
+
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    assert_eq!(connection.state(), State::Open);
+
+    connection.resume_receiving();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(connection.state(), State::Open);
+
+    let idle = events_rx
+        .drain()
+        .any(|event| event.kind() == EventKind::IdleTimeout);
+    assert!(!idle);
+}
+
+#[tokio::test]
+async fn paused_connection_leaked_without_handles_stops() {
+    let (addr, _server) = collecting_server().await;
+
+    let mut connection = assert_ok!(gateway::connect(addr).await);
+    let events_rx = assert_ok!(connection.observe_events().await);
+    connection.set_drop_policy(DropPolicy::Leak);
+    connection.pause_receiving();
+    drop(connection);
+
+    let finished = tokio::time::timeout(Duration::from_secs(1), async {
+        while let Ok(event) = events_rx.recv_async().await {
+            if let EventKind::CloseFinished { code } = event.kind() {
+                return code;
+            }
+        }
+
+        None
+    })
+    .await;
+    assert_eq!(assert_ok!(finished), Some(StatusCode::AbnormalClosure));
+}
+
+#[tokio::test]
+async fn handover_carries_scheduled_messages() {
+    let (addr, server) = collecting_server().await;
+
+    let connection = assert_ok!(gateway::connect(addr).await);
+    let handle = assert_ok!(
+        connection
+            .send_after(
+                Message::from(Text::from("later")),
+                Duration::from_millis(100)
+            )
+            .await
+    );
+
+    let (stream, state) = assert_ok!(connection.into_inner().await);
+    assert_eq!(state.scheduled_messages(), 1);
+    assert!(!handle.cancel());
+
+    let connection = assert_ok!(Connection::from_state(
+        stream,
+        state,
+        WebSocketConfig::new()
+    ));
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    connection.stop().await;
+
+    let (received, closed) = assert_ok!(server.await);
+    assert_eq!(received, ["later"]);
+    assert_matches!(closed, WebSocketError::ConnectionClosed);
+}
+
+#[tokio::test]
+async fn oversized_control_payloads_report_their_opcode() {
+    let (addr, server) = collecting_server().await;
+
+    let config = WebSocketConfig::new().with_strict_close_reason(true);
+    let connection = assert_ok!(gateway::connect_with_config(addr, "/", config).await);
+
+    assert_matches!(
+        connection
+            .ping(Binary::from(vec![0; 126]), Duration::from_secs(1))
+            .await,
+        Err(WebSocketError::InvalidFrame(
+            InvalidFrame::ControlTooLarge {
+                opcode: 0x9,
+                len: 126
+            }
+        ))
+    );
+    assert_matches!(
+        connection.pong(Binary::from(vec![0; 126])).await,
+        Err(WebSocketError::InvalidFrame(
+            InvalidFrame::ControlTooLarge {
+                opcode: 0xA,
+                len: 126
+            }
+        ))
+    );
+    let reason = Text::from("a".repeat(124));
+    assert_matches!(
+        connection
+            .close(StatusCode::NormalClosure, Some(reason))
+            .await,
+        Err(WebSocketError::InvalidFrame(
+            InvalidFrame::ControlTooLarge {
+                opcode: 0x8,
+                len: 126
+            }
+        ))
+    );
+
+    connection.stop().await;
+    assert_ok!(server.await);
+}
+
+#[tokio::test]
+async fn send_buf_sends_the_remaining_bytes_as_binary() {
+    let listener = assert_ok!(TcpListener::bind("127.0.0.1:0").await);
+    let addr = assert_ok!(listener.local_addr());
+
+    let server = tokio::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let connection = gateway::accept(stream).await.unwrap();
+
+        connection.receive().await
+    });
+
+    let connection = assert_ok!(gateway::connect(addr).await);
+    let mut buf = (&b"--hel"[..]).chain(&b"lo"[..]);
+    buf.advance(2);
+    assert_ok!(connection.send_buf(buf).await);
+
+    let received = assert_ok!(server.await);
+    assert_matches!(received, Ok(Message::Binary(binary)) if &*binary == b"hello");
+
+    connection.stop().await;
+}
+
+async fn received_fragments(config: WebSocketConfig, text: &str) -> usize {
+    let listener = assert_ok!(TcpListener::bind("127.0.0.1:0").await);
+    let addr = assert_ok!(listener.local_addr());
+
+    let server = tokio::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let connection = gateway::accept(stream).await.unwrap();
+
+        connection.receive_envelope().await
+    });
+
+    let connection = assert_ok!(gateway::connect_with_config(addr, "/", config).await);
+    connection.set_fragment_size(4);
+    assert_ok!(connection.send(Message::from(Text::from(text))).await);
+
+    let envelope = assert_ok!(assert_ok!(server.await));
+    assert_matches!(envelope.message(), Message::Text(received) if &**received == text);
+    connection.stop().await;
+
+    envelope.fragments()
+}
+
+#[tokio::test]
+async fn text_fragments_end_on_char_boundaries_by_default() {
+    let fragments = received_fragments(WebSocketConfig::new(), "aéaéaéaé").await;
+
+    assert_eq!(fragments, 4);
+}
+
+#[tokio::test]
+async fn raw_text_fragments_split_at_byte_positions() {
+    let config = WebSocketConfig::new().with_raw_text_fragments(true);
+    let fragments = received_fragments(config, "aéaéaéaé").await;
+
+    assert_eq!(fragments, 3);
+}
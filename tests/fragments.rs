@@ -0,0 +1,230 @@
+use std::time::Duration;
+
+use claim::{assert_matches, assert_ok};
+use websocket::{
+    connection::{Connection, ControlKind, EventKind, State, StatusCode, WebSocketConfig},
+    error::{InvalidFrame, WebSocketError},
+    gateway,
+    message::Message,
+    testing::{MockServer, Script},
+};
+
+#[tokio::test]
+async fn ping_between_text_fragments() {
+    let script = Script::new()
+        .delay(Duration::from_millis(50))
+        .frame(false, 0x1, "Hel")
+        .ping(b"keepalive")
+        .frame(true, 0x0, "lo")
+        .expect();
+    let server = assert_ok!(MockServer::start(script).await);
+
+    let connection = assert_ok!(gateway::connect(server.addr()).await);
+    let control_rx = assert_ok!(connection.observe_control_frames().await);
+
+    let message = assert_ok!(connection.receive().await);
+    assert_matches!(message, Message::Text(text) if &*text == "Hello");
+
+    let control = assert_ok!(control_rx.recv_async().await);
+    assert_eq!(control.kind(), ControlKind::Ping);
+    assert_eq!(&**control.payload(), b"keepalive");
+}
+
+#[tokio::test]
+async fn pings_between_every_binary_fragment() {
+    let script = Script::new()
+        .frame(false, 0x2, &b"\x01\x02"[..])
+        .ping(b"a")
+        .frame(false, 0x0, &b"\x03"[..])
+        .ping(b"b")
+        .frame(true, 0x0, &b"\x04"[..])
+        .binary(b"\x05")
+        .expect()
+        .expect();
+    let server = assert_ok!(MockServer::start(script).await);
+
+    let connection = assert_ok!(gateway::connect(server.addr()).await);
+
+    let message = assert_ok!(connection.receive().await);
+    assert_matches!(message, Message::Binary(binary) if &*binary == b"\x01\x02\x03\x04");
+
+    let message = assert_ok!(connection.receive().await);
+    assert_matches!(message, Message::Binary(binary) if &*binary == b"\x05");
+}
+
+#[tokio::test]
+async fn pong_between_fragments_splitting_a_character() {
+    let script = Script::new()
+        .frame(false, 0x1, &b"caf\xC3"[..])
+        .frame(true, 0xA, "unsolicited")
+        .frame(true, 0x0, &b"\xA9"[..]);
+    let server = assert_ok!(MockServer::start(script).await);
+
+    let connection = assert_ok!(gateway::connect(server.addr()).await);
+
+    let message = assert_ok!(connection.receive().await);
+    assert_matches!(message, Message::Text(text) if &*text == "café");
+}
+
+#[tokio::test]
+async fn close_between_fragments() {
+    let script = Script::new()
+        .frame(false, 0x1, "partial")
+        .close(1001, "going away")
+        .expect();
+    let server = assert_ok!(MockServer::start(script).await);
+
+    let connection = assert_ok!(gateway::connect(server.addr()).await);
+
+    assert_matches!(
+        connection.receive().await,
+        Err(WebSocketError::ConnectionClosed)
+    );
+
+    let close_reason = connection.close_reason().unwrap();
+    assert_eq!(close_reason.code(), StatusCode::GoingAway);
+    assert_eq!(
+        close_reason.reason().map(|reason| &**reason),
+        Some("going away")
+    );
+}
+
+#[tokio::test]
+async fn fragmented_ping_is_rejected() {
+    let script = Script::new()
+        .frame(false, 0x1, "Hel")
+        .frame(false, 0x9, "ping")
+        .frame(true, 0x0, "lo");
+    let server = assert_ok!(MockServer::start(script).await);
+
+    let connection = assert_ok!(gateway::connect(server.addr()).await);
+
+    assert_matches!(
+        connection.receive().await,
+        Err(WebSocketError::InvalidFrame(_))
+    );
+}
+
+#[tokio::test]
+async fn invalid_utf8_fails_before_the_final_fragment() {
+    let script = Script::new()
+        .delay(Duration::from_millis(50))
+        .frame(false, 0x1, "valid")
+        .frame(false, 0x0, &b"\xC3\x28"[..])
+        .expect()
+        .delay(Duration::from_secs(5));
+    let server = assert_ok!(MockServer::start(script).await);
+
+    let connection = assert_ok!(gateway::connect(server.addr()).await);
+    let events_rx = assert_ok!(connection.observe_events().await);
+
+    assert_matches!(
+        connection.receive().await,
+        Err(WebSocketError::InvalidFrame(InvalidFrame::Text(_)))
+    );
+
+    let closing = events_rx.drain().any(|event| {
+        event.kind()
+            == EventKind::CloseStarted {
+                local: true,
+                code: Some(StatusCode::InconsistentData),
+            }
+    });
+    assert!(closing);
+}
+
+#[tokio::test]
+async fn envelope_reports_fragments_and_wire_size() {
+    let script = Script::new()
+        .frame(false, 0x1, "Hel")
+        .frame(false, 0x0, "l")
+        .frame(true, 0x0, "o")
+        .binary(&[0; 200]);
+    let server = assert_ok!(MockServer::start(script).await);
+
+    let connection = assert_ok!(gateway::connect(server.addr()).await);
+
+    let envelope = assert_ok!(connection.receive_envelope().await);
+    assert_matches!(envelope.message(), Message::Text(text) if &**text == "Hello");
+    assert_eq!(envelope.fragments(), 3);
+    assert_eq!(envelope.wire_size(), 11);
+
+    let envelope = assert_ok!(connection.receive_envelope().await);
+    assert_eq!(envelope.fragments(), 1);
+    assert_eq!(envelope.wire_size(), 204);
+}
+
+#[tokio::test]
+async fn too_many_fragments_close_cleanly() {
+    let script = Script::new()
+        .frame(false, 0x1, "a")
+        .frame(false, 0x0, "b")
+        .frame(false, 0x0, "c")
+        .frame(true, 0x0, "d")
+        .expect()
+        .close(1009, "");
+    let server = assert_ok!(MockServer::start(script).await);
+
+    let config = WebSocketConfig::new().with_max_fragments(2);
+    let connection = assert_ok!(gateway::connect_with_config(server.addr(), "/", config).await);
+
+    assert_matches!(
+        connection.receive().await,
+        Err(WebSocketError::ConnectionClosed)
+    );
+    assert_eq!(
+        connection.state(),
+        State::Closed {
+            code: Some(StatusCode::MessageTooBig)
+        }
+    );
+}
+
+#[tokio::test]
+async fn oversized_fragmented_message_closes_cleanly() {
+    let script = Script::new()
+        .frame(false, 0x2, &[0; 6][..])
+        .frame(false, 0x0, &[0; 6][..])
+        .frame(false, 0x0, &[0; 6][..])
+        .frame(true, 0x0, &[0; 6][..])
+        .expect()
+        .close(1009, "");
+    let server = assert_ok!(MockServer::start(script).await);
+
+    let config = WebSocketConfig::new().with_max_message_size(8);
+    let connection = assert_ok!(gateway::connect_with_config(server.addr(), "/", config).await);
+
+    assert_matches!(
+        connection.receive().await,
+        Err(WebSocketError::ConnectionClosed)
+    );
+    assert_eq!(
+        connection.state(),
+        State::Closed {
+            code: Some(StatusCode::MessageTooBig)
+        }
+    );
+}
+
+#[tokio::test]
+async fn handover_between_fragments() {
+    let script = Script::new()
+        .frame(false, 0x1, "Hel")
+        .delay(Duration::from_millis(200))
+        .frame(true, 0x0, "lo");
+    let server = assert_ok!(MockServer::start(script).await);
+
+    let connection = assert_ok!(gateway::connect(server.addr()).await);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let (stream, state) = assert_ok!(connection.into_inner().await);
+    assert!(state.has_partial_message());
+
+    let connection = assert_ok!(Connection::from_state(
+        stream,
+        state,
+        WebSocketConfig::new()
+    ));
+    let message = assert_ok!(connection.receive().await);
+    assert_matches!(message, Message::Text(text) if &*text == "Hello");
+}
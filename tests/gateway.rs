@@ -0,0 +1,131 @@
+use std::time::Duration;
+
+use claim::{assert_matches, assert_ok};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+use websocket::{
+    connection::WebSocketConfig,
+    error::{InvalidHandshake, WebSocketError},
+    gateway,
+    message::{Message, Text},
+};
+
+async fn peek_past_request_head(stream: &TcpStream) -> usize {
+    let mut peeked = [0; 1024];
+
+    loop {
+        let read = stream.peek(&mut peeked).await.unwrap();
+        if let Some(end) = peeked[..read].windows(4).position(|w| w == b"\r\n\r\n")
+            && read > end + 4
+        {
+            return read - (end + 4);
+        }
+
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+}
+
+#[tokio::test]
+async fn connect_and_send_pipelines_the_first_message() {
+    let listener = assert_ok!(TcpListener::bind("127.0.0.1:0").await);
+    let addr = assert_ok!(listener.local_addr());
+
+    let server = tokio::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let pipelined =
+            tokio::time::timeout(Duration::from_secs(1), peek_past_request_head(&stream))
+                .await
+                .unwrap();
+
+        let connection = gateway::accept(stream).await.unwrap();
+
+        (pipelined, connection.receive().await)
+    });
+
+    let message = Message::from(Text::from("hello"));
+    let connection =
+        assert_ok!(gateway::connect_and_send(addr, "/", WebSocketConfig::new(), message).await);
+
+    let (pipelined, received) = assert_ok!(server.await);
+    assert_eq!(pipelined, 2 + 4 + 5);
+    assert_matches!(received, Ok(Message::Text(text)) if &*text == "hello");
+
+    connection.stop().await;
+}
+
+#[tokio::test]
+async fn connect_and_send_reports_a_rejected_handshake() {
+    let listener = assert_ok!(TcpListener::bind("127.0.0.1:0").await);
+    let addr = assert_ok!(listener.local_addr());
+
+    tokio::spawn(async move {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut request = [0; 1024];
+        let _ = stream.read(&mut request).await.unwrap();
+        stream
+            .write_all(b"HTTP/1.1 403 Forbidden\r\nContent-Length: 0\r\n\r\n")
+            .await
+            .unwrap();
+    });
+
+    let message = Message::from(Text::from("hello"));
+    let Err(error) = gateway::connect_and_send(addr, "/", WebSocketConfig::new(), message).await
+    else {
+        panic!("handshake should have been rejected");
+    };
+    assert_matches!(error, WebSocketError::HandshakeRejected { status: 403, .. });
+}
+
+async fn rejected_request(request: Vec<u8>) -> (Vec<u8>, WebSocketError) {
+    let listener = assert_ok!(TcpListener::bind("127.0.0.1:0").await);
+    let addr = assert_ok!(listener.local_addr());
+
+    let server = tokio::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+
+        gateway::accept(stream).await
+    });
+
+    let mut stream = assert_ok!(TcpStream::connect(addr).await);
+    assert_ok!(stream.write_all(&request).await);
+    let mut response = Vec::new();
+    assert_ok!(stream.read_to_end(&mut response).await);
+
+    let Err(error) = assert_ok!(server.await) else {
+        panic!("handshake should have been rejected");
+    };
+
+    (response, error)
+}
+
+#[tokio::test]
+async fn accept_rejects_a_request_line_that_is_too_long() {
+    let mut request = b"GET /".to_vec();
+    request.resize(9 * 1024, b'a');
+    request.extend_from_slice(b" HTTP/1.1\r\n");
+
+    let (response, error) = rejected_request(request).await;
+
+    assert!(response.starts_with(b"HTTP/1.1 414 URI Too Long\r\n"));
+    assert_matches!(
+        error,
+        WebSocketError::InvalidHandshake(InvalidHandshake::RequestLineTooLong)
+    );
+}
+
+#[tokio::test]
+async fn accept_rejects_a_head_that_is_too_large() {
+    let mut request = b"GET / HTTP/1.1\r\nX-Padding: ".to_vec();
+    request.resize(16 * 1024 - 2, b'a');
+    request.extend_from_slice(b"\r\n");
+
+    let (response, error) = rejected_request(request).await;
+
+    assert!(response.starts_with(b"HTTP/1.1 431 Request Header Fields Too Large\r\n"));
+    assert_matches!(
+        error,
+        WebSocketError::InvalidHandshake(InvalidHandshake::HeadTooLarge)
+    );
+}
@@ -0,0 +1,56 @@
+use std::net::SocketAddr;
+
+use claim::{assert_matches, assert_ok};
+use websocket::{
+    connection::WebSocketConfig,
+    error::InvalidHandshake,
+    handshake::{client::Builder, server::Responder},
+};
+
+fn addr() -> SocketAddr {
+    "127.0.0.1:9001".parse().unwrap()
+}
+
+fn contains(raw: &[u8], needle: &[u8]) -> bool {
+    raw.windows(needle.len()).any(|window| window == needle)
+}
+
+#[test]
+fn builder_applies_the_config() {
+    let config = WebSocketConfig::new()
+        .with_metadata("tenant", "a b")
+        .with_protocol_versions([8, 13]);
+
+    let request = Builder::new(addr(), "/").with_config(&config).raw_request();
+
+    assert!(contains(&request, b"\r\nSec-WebSocket-Version: 8\r\n"));
+    assert!(contains(&request, b"\r\nX-WS-Meta: tenant=a%20b"));
+}
+
+#[test]
+fn configured_handshake_round_trips() {
+    let config = WebSocketConfig::new().with_metadata("tenant", "a b");
+    let builder = Builder::new(addr(), "/chat").with_config(&config);
+
+    let responder = assert_ok!(Responder::from_request(&builder.raw_request(), &config));
+    assert_eq!(
+        responder.info().metadata_value("tenant").as_deref(),
+        Some("a b")
+    );
+    let (response, _) = responder.into_raw_response();
+
+    let info = assert_ok!(builder.validate_response(&response));
+    assert_eq!(info.path(), "/chat");
+    assert_eq!(info.metadata_value("tenant").as_deref(), Some("a b"));
+}
+
+#[test]
+fn responder_refuses_an_unsupported_version() {
+    let config = WebSocketConfig::new().with_protocol_versions([8]);
+    let builder = Builder::new(addr(), "/").with_config(&config);
+
+    assert_matches!(
+        Responder::from_request(&builder.raw_request(), &WebSocketConfig::new()),
+        Err(InvalidHandshake::NonConformant(_))
+    );
+}
@@ -0,0 +1,124 @@
+use std::time::{Duration, Instant};
+
+use claim::{assert_matches, assert_ok};
+use websocket::{
+    connection::{Connection, Limits, RatePolicy, State, StatusCode},
+    error::WebSocketError,
+    gateway,
+    message::Message,
+    testing::{MockServer, Script},
+};
+
+async fn connect(server: &MockServer, limits: Limits) -> Connection {
+    let mut connection = assert_ok!(gateway::connect(server.addr()).await);
+    connection.set_limits(limits);
+
+    connection
+}
+
+async fn assert_closed_for_policy_violation(connection: &Connection) {
+    assert_matches!(
+        connection.receive().await,
+        Err(WebSocketError::ConnectionClosed)
+    );
+    assert_eq!(
+        connection.state(),
+        State::Closed {
+            code: Some(StatusCode::PolicyViolation)
+        }
+    );
+}
+
+#[tokio::test]
+async fn message_rate_limit_closes_with_policy_violation() {
+    let script = Script::new()
+        .delay(Duration::from_millis(50))
+        .text("a")
+        .text("b")
+        .text("c")
+        .expect()
+        .close(1008, "");
+    let server = assert_ok!(MockServer::start(script).await);
+
+    let limits = Limits::new().with_rate_limit(2, Duration::from_secs(10));
+    let connection = connect(&server, limits).await;
+
+    assert_matches!(connection.receive().await, Ok(Message::Text(text)) if &*text == "a");
+    assert_matches!(connection.receive().await, Ok(Message::Text(text)) if &*text == "b");
+    assert_closed_for_policy_violation(&connection).await;
+}
+
+#[tokio::test]
+async fn frame_rate_limit_discards_the_rest_of_the_message() {
+    let script = Script::new()
+        .delay(Duration::from_millis(50))
+        .frame(false, 0x1, "a")
+        .frame(false, 0x0, "b")
+        .frame(false, 0x0, "c")
+        .frame(true, 0x0, "d")
+        .expect()
+        .close(1008, "");
+    let server = assert_ok!(MockServer::start(script).await);
+
+    let limits = Limits::new().with_frame_rate_limit(2, Duration::from_secs(10));
+    let connection = connect(&server, limits).await;
+
+    assert_closed_for_policy_violation(&connection).await;
+}
+
+#[tokio::test]
+async fn byte_rate_limit_closes_with_policy_violation() {
+    let script = Script::new()
+        .delay(Duration::from_millis(50))
+        .binary(&[0; 3])
+        .binary(&[0; 3])
+        .expect()
+        .close(1008, "");
+    let server = assert_ok!(MockServer::start(script).await);
+
+    let limits = Limits::new().with_byte_rate_limit(4, Duration::from_secs(10));
+    let connection = connect(&server, limits).await;
+
+    assert_matches!(connection.receive().await, Ok(Message::Binary(binary)) if binary.len() == 3);
+    assert_closed_for_policy_violation(&connection).await;
+}
+
+#[tokio::test]
+async fn control_rate_limit_closes_with_policy_violation() {
+    let script = Script::new()
+        .delay(Duration::from_millis(50))
+        .ping(b"a")
+        .ping(b"b")
+        .expect()
+        .expect()
+        .close(1008, "");
+    let server = assert_ok!(MockServer::start(script).await);
+
+    let limits = Limits::new().with_control_rate_limit(1, Duration::from_secs(10));
+    let connection = connect(&server, limits).await;
+
+    assert_closed_for_policy_violation(&connection).await;
+}
+
+#[tokio::test]
+async fn throttle_policy_delays_instead_of_closing() {
+    let script = Script::new()
+        .delay(Duration::from_millis(50))
+        .text("a")
+        .text("b")
+        .text("c")
+        .delay(Duration::from_secs(5));
+    let server = assert_ok!(MockServer::start(script).await);
+
+    let started = Instant::now();
+    let limits = Limits::new()
+        .with_rate_limit(1, Duration::from_millis(200))
+        .with_rate_policy(RatePolicy::Throttle);
+    let connection = connect(&server, limits).await;
+
+    for expected in ["a", "b", "c"] {
+        assert_matches!(connection.receive().await, Ok(Message::Text(text)) if &*text == expected);
+    }
+    assert!(started.elapsed() >= Duration::from_millis(200));
+    assert_eq!(connection.state(), State::Open);
+}
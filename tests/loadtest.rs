@@ -0,0 +1,34 @@
+use claim::assert_ok;
+use tokio::net::TcpListener;
+use websocket::{gateway, loadtest::LoadTest};
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn every_client_round_trips_against_an_echo_peer() {
+    let listener = assert_ok!(TcpListener::bind("127.0.0.1:0").await);
+    let addr = assert_ok!(listener.local_addr());
+
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let connection = gateway::accept(stream).await.unwrap();
+                while let Ok(message) = connection.receive().await {
+                    if connection.send(message).await.is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+    });
+
+    let report = assert_ok!(
+        LoadTest::new(4)
+            .messages_per_client(10)
+            .message_size(16)
+            .run(addr)
+            .await
+    );
+
+    assert_eq!(report.clients(), 4);
+    assert_eq!(report.messages(), 40);
+    assert_eq!(report.bytes(), 40 * 16);
+}
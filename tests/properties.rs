@@ -0,0 +1,90 @@
+use std::time::Duration;
+
+use bytes::Bytes;
+use proptest::prelude::*;
+use websocket::{
+    connection::WebSocketConfig,
+    error::{InvalidFrame, WebSocketError},
+    gateway,
+    handshake::server::Responder,
+    message::Message,
+    testing::{
+        Frame, MockServer, Script,
+        strategy::{fragmented_message, frame, handshake_request},
+    },
+};
+
+fn script(frames: Vec<Frame>) -> Script {
+    frames.into_iter().fold(Script::new(), Script::push)
+}
+
+fn receive_from(script: Script) -> Result<Message, WebSocketError> {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+
+    runtime.block_on(async move {
+        let server = MockServer::start(script).await?;
+        let connection = gateway::connect(server.addr()).await?;
+
+        tokio::time::timeout(Duration::from_secs(5), connection.receive())
+            .await
+            .expect("receive should not hang")
+    })
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(64))]
+
+    #[test]
+    fn generated_handshake_requests_are_accepted(raw in handshake_request()) {
+        prop_assert!(Responder::from_request(&raw, &WebSocketConfig::new()).is_ok());
+    }
+
+    #[test]
+    fn fragmented_messages_are_reassembled(frames in fragmented_message(false, 8, 64)) {
+        let text = frames[0].opcode == 0x1;
+        let payload: Vec<u8> = frames
+            .iter()
+            .filter(|frame| matches!(frame.opcode, 0x0..=0x2))
+            .flat_map(|frame| frame.payload.iter().copied())
+            .collect();
+
+        match receive_from(script(frames).delay(Duration::from_secs(10))) {
+            Ok(Message::Text(received)) => {
+                prop_assert!(text);
+                prop_assert_eq!(received.as_bytes(), &payload[..]);
+            }
+            Ok(Message::Binary(received)) => {
+                prop_assert!(!text);
+                prop_assert_eq!(received.as_bytes(), &payload[..]);
+            }
+            Err(WebSocketError::InvalidFrame(InvalidFrame::Text(_))) => {
+                prop_assert!(text && std::str::from_utf8(&payload).is_err());
+            }
+            Err(e) => prop_assert!(false, "unexpected error: {}", e),
+        }
+    }
+
+    #[test]
+    fn arbitrary_frames_never_hang_the_client(frame in frame(false, 256)) {
+        let delivered = frame.fin
+            && frame.rsv == 0
+            && matches!(frame.opcode, 0x1 | 0x2)
+            && (frame.opcode == 0x2 || std::str::from_utf8(&frame.payload).is_ok());
+        let payload: Bytes = frame.payload.clone();
+
+        match receive_from(script(vec![frame])) {
+            Ok(message) => {
+                prop_assert!(delivered);
+                let received = match &message {
+                    Message::Text(text) => text.as_bytes(),
+                    Message::Binary(binary) => binary.as_bytes(),
+                };
+                prop_assert_eq!(received, &payload[..]);
+            }
+            Err(_) => prop_assert!(!delivered),
+        }
+    }
+}
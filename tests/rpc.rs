@@ -0,0 +1,24 @@
+use std::time::Duration;
+
+use claim::{assert_matches, assert_ok};
+use tokio::net::TcpListener;
+use websocket::{error::WebSocketError, gateway, rpc::RpcConnection};
+
+#[tokio::test]
+async fn dropping_rpc_connection_closes_it() {
+    let listener = assert_ok!(TcpListener::bind("127.0.0.1:0").await);
+    let addr = assert_ok!(listener.local_addr());
+
+    let server = tokio::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let connection = gateway::accept(stream).await.unwrap();
+
+        connection.receive().await
+    });
+
+    let rpc = RpcConnection::new(assert_ok!(gateway::connect(addr).await));
+    drop(rpc);
+
+    let received = assert_ok!(tokio::time::timeout(Duration::from_secs(2), server).await);
+    assert_matches!(assert_ok!(received), Err(WebSocketError::ConnectionClosed));
+}
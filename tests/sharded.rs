@@ -0,0 +1,32 @@
+use std::time::Duration;
+
+use claim::{assert_matches, assert_ok};
+use futures_util::future::join_all;
+use tokio::net::TcpListener;
+use websocket::{
+    connection::WebSocketConfig, error::WebSocketError, gateway, sharded::ShardedClient,
+};
+
+#[tokio::test]
+async fn dropping_sharded_client_closes_every_shard() {
+    let listener = assert_ok!(TcpListener::bind("127.0.0.1:0").await);
+    let addr = assert_ok!(listener.local_addr());
+
+    let server = tokio::spawn(async move {
+        let mut connections = Vec::new();
+        for _ in 0..3 {
+            let (stream, _) = listener.accept().await.unwrap();
+            connections.push(gateway::accept(stream).await.unwrap());
+        }
+
+        join_all(connections.iter().map(|connection| connection.receive())).await
+    });
+
+    let client = assert_ok!(ShardedClient::connect(addr, "/", 3, WebSocketConfig::new()).await);
+    drop(client);
+
+    let received = assert_ok!(tokio::time::timeout(Duration::from_secs(2), server).await);
+    for received in assert_ok!(received) {
+        assert_matches!(received, Err(WebSocketError::ConnectionClosed));
+    }
+}
@@ -0,0 +1,33 @@
+use claim::{assert_matches, assert_ok};
+use websocket::{
+    connection::WebSocketConfig,
+    error::WebSocketError,
+    gateway,
+    testing::{MockServer, Script},
+};
+
+#[tokio::test]
+async fn upgrade_required_retries_with_an_offered_version() {
+    let server = assert_ok!(MockServer::start(Script::new()).await);
+    let config = WebSocketConfig::new().with_protocol_versions([8, 13]);
+
+    let connection = assert_ok!(gateway::connect_with_config(server.addr(), "/", config).await);
+
+    assert_eq!(connection.handshake_info().version(), 13);
+    connection.stop().await;
+}
+
+#[tokio::test]
+async fn upgrade_required_without_a_common_version_is_rejected() {
+    let server = assert_ok!(MockServer::start(Script::new()).await);
+    let config = WebSocketConfig::new().with_protocol_versions([7, 8]);
+
+    let Err(error) = gateway::connect_with_config(server.addr(), "/", config).await else {
+        panic!("handshake should have been rejected");
+    };
+    assert_matches!(
+        error,
+        WebSocketError::HandshakeRejected { status: 426, ref headers, .. }
+            if headers.iter().any(|(name, value)| name == "Sec-WebSocket-Version" && value == "13")
+    );
+}